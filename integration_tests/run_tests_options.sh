@@ -0,0 +1,149 @@
+#!/bin/bash -ue
+
+# Tests for behavior that depends on command line options. Each section starts
+# its own instance of hypershare serving a fresh directory.
+
+set -m # Enable jobs
+
+YELLOW='\033[0;33m'
+NC='\033[0m' # No Color
+
+export PORT=12390
+export BOUNDARY="aaaaaaaaaaaaaaaaaaaa" # 20 a's
+
+cargo build
+
+set +e
+
+pushd $(dirname ${BASH_SOURCE[0]}) > /dev/null
+
+function errored() {
+    echo -e "${YELLOW}!!! Test errored${NC}"
+}
+
+# Set FD_LIMIT to lower the open file limit of the next instance
+function start_hypershare() {
+    export DIR=$(mktemp -d)
+    echo "Starting hypershare with: $@"
+    (ulimit -n ${FD_LIMIT:-$(ulimit -n)}; \
+        exec ../target/debug/hypershare -d $DIR -p $PORT -m "127.0.0.1" --headless "$@") \
+        | sed -e 's/^/ >>> hypershare: /g' &
+    sleep 1
+}
+
+function stop_hypershare() {
+    kill -2 %1
+    wait %1
+    rm -r $DIR
+}
+
+echo -e "\n.... Upload size limit ...."
+
+start_hypershare -u --upload-size-limit 1024
+dd if=/dev/urandom of=$DIR/test_1m.img bs=1K count=1K 2>&1 > /dev/null
+echo ":)" > $DIR/test_small.img
+
+echo "TEST: Raw POST under the limit... "
+templates/raw_post_request.sh test_small.img || errored
+
+echo "TEST: Raw POST over the limit... "
+templates/raw_post_request.sh test_1m.img 413 || errored
+
+stop_hypershare
+
+echo -e "\n.... Method allowlist ...."
+
+start_hypershare -u --methods GET,HEAD
+echo ":)" > $DIR/test_small.img
+
+echo "TEST: GET is allowed... "
+templates/expect_status.sh 200 http://localhost:$PORT/test_small.img || errored
+
+echo "TEST: POST is rejected even with uploading enabled... "
+templates/expect_status.sh 405 --data-binary "@$DIR/test_small.img" \
+    http://localhost:$PORT/allowlist.img || errored
+
+echo "TEST: Rejection lists the allowed methods... "
+templates/expect_header.sh "Allow: GET, HEAD$" --data-binary "@$DIR/test_small.img" \
+    http://localhost:$PORT/allowlist.img || errored
+
+stop_hypershare
+
+echo -e "\n.... Reserved paths ...."
+
+start_hypershare --security-contact mailto:admin@example.com
+
+echo "TEST: Default robots.txt... "
+templates/expect_body.sh "^Disallow: /$" http://localhost:$PORT/robots.txt || errored
+
+echo "TEST: Default security.txt... "
+templates/expect_body.sh "^Contact: mailto:admin@example.com$" \
+    http://localhost:$PORT/.well-known/security.txt || errored
+
+echo "TEST: Real robots.txt wins... "
+echo "User-agent: friendly-bot" > $DIR/robots.txt
+templates/expect_body.sh "^User-agent: friendly-bot$" http://localhost:$PORT/robots.txt || errored
+
+stop_hypershare
+
+start_hypershare --robots none
+
+echo "TEST: No robots.txt without a policy... "
+templates/expect_status.sh 404 http://localhost:$PORT/robots.txt || errored
+
+echo "TEST: No security.txt without a contact... "
+templates/expect_status.sh 404 http://localhost:$PORT/.well-known/security.txt || errored
+
+stop_hypershare
+
+echo -e "\n.... Range requests ...."
+
+start_hypershare
+echo "0123456789" > $DIR/test_small.img
+
+echo "TEST: Range request is honored by default... "
+templates/expect_status.sh 206 -r 2-4 http://localhost:$PORT/test_small.img || errored
+
+stop_hypershare
+
+start_hypershare --no-ranges
+echo "0123456789" > $DIR/test_small.img
+
+echo "TEST: Range request returns the full file with --no-ranges... "
+templates/expect_status.sh 200 -r 2-4 http://localhost:$PORT/test_small.img || errored
+templates/expect_header.sh "Content-Length: 11$" -r 2-4 http://localhost:$PORT/test_small.img \
+    || errored
+
+echo "TEST: Accept-Ranges is not advertised with --no-ranges... "
+templates/expect_header.sh "!Accept-Ranges" http://localhost:$PORT/test_small.img || errored
+
+stop_hypershare
+
+echo -e "\n.... Watching for changes ...."
+
+start_hypershare --watch
+
+echo "TEST: Creating a file sends an event... "
+templates/expect_event.sh new_file.img || errored
+
+stop_hypershare
+
+start_hypershare
+
+echo "TEST: No event stream without --watch... "
+templates/expect_status.sh 404 http://localhost:$PORT/__events || errored
+
+stop_hypershare
+
+echo -e "\n.... Running out of file descriptors ...."
+
+FD_LIMIT=32 start_hypershare
+
+echo "TEST: Running out of file descriptors does not spin... "
+templates/expect_no_spin.sh 40 || errored
+
+stop_hypershare
+
+echo -e "...................................\n"
+
+popd > /dev/null
@@ -69,6 +69,20 @@ templates/wellformed_post_request.sh test_small.img || errored
 echo "TEST: Small file with expectation... "
 templates/wellformed_post_request_with_continue.sh test_small.img || errored
 
+echo -e "\n.... Raw POST Requests (curl) ...."
+
+echo "TEST: 1M file... "
+templates/raw_post_request.sh test_1m.img || errored
+
+echo "TEST: 0B file... "
+templates/raw_post_request.sh test_0b.img || errored
+
+echo "TEST: Small file... "
+templates/raw_post_request.sh test_small.img || errored
+
+echo "TEST: File with spaces... "
+templates/raw_post_request.sh "file with spaces and %s" || errored
+
 echo -e "\n.... GET + POST Requests (curl/wget) ...."
 
 echo "TEST: 1M file... "
@@ -83,6 +97,14 @@ templates/curl_wget_twoway.sh test_small.img || errored
 echo "TEST: File with spaces... "
 templates/curl_wget_twoway.sh "file with spaces and %s" || errored
 
+echo -e "\n.... Malformed Requests ...."
+
+echo "TEST: Content-Length with Transfer-Encoding... "
+templates/expect_raw_response.sh 400 close "POST /smuggled.img HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n:)\n" || errored
+
+echo "TEST: Overly long method... "
+templates/expect_raw_response.sh 400 close "$(head -c 2048 /dev/zero | tr '\0' A)" || errored
+
 echo -e "...................................\n"
 echo "Killing hypershare and cleaning up"
 
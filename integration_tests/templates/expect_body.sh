@@ -0,0 +1,34 @@
+#!/bin/bash -ue
+
+# Usage: expect_body.sh <regex> <curl arguments...>
+# A regex starting with '!' asserts that no line of the body matches.
+
+pattern="$1"
+shift
+
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+NC='\033[0m' # No Color
+
+body=$(curl -s "$@")
+
+if [[ "$pattern" == !* ]]
+then
+    if echo "$body" | grep -qE -- "${pattern:1}"
+    then
+        echo -e "${RED}Failed!!!${NC}"
+        echo "Found unexpected match for '${pattern:1}' in:"
+        echo "$body"
+    else
+        echo -e "${GREEN}Passed${NC}"
+    fi
+else
+    if echo "$body" | grep -qE -- "$pattern"
+    then
+        echo -e "${GREEN}Passed${NC}"
+    else
+        echo -e "${RED}Failed!!!${NC}"
+        echo "Could not find '$pattern' in:"
+        echo "$body"
+    fi
+fi
@@ -0,0 +1,35 @@
+#!/bin/bash -ue
+
+# Usage: expect_event.sh <file>
+# Subscribes to /__events, creates <file> in the served directory, and checks
+# that an event naming it was received.
+
+file="$1"
+
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+NC='\033[0m' # No Color
+
+events=$(mktemp)
+
+curl -sN "http://localhost:$PORT/__events" > $events &
+curl_pid=$!
+sleep 1
+
+touch "$DIR/$file"
+sleep 1
+
+kill $curl_pid
+wait $curl_pid 2>/dev/null || true
+
+if grep -q "^data: /$file\$" $events
+then
+    echo -e "${GREEN}Passed${NC}"
+else
+    echo -e "${RED}Failed!!!${NC}"
+    echo "Did not receive an event for /$file:"
+    cat $events
+fi
+
+rm $events
+rm "$DIR/$file"
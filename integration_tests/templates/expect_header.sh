@@ -0,0 +1,36 @@
+#!/bin/bash -ue
+
+# Usage: expect_header.sh <header line regex> <curl arguments...>
+# A regex starting with '!' asserts that no header line matches.
+
+pattern="$1"
+shift
+
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+NC='\033[0m' # No Color
+
+CR=$(echo -ne '\r')
+
+headers=$(curl -s -D - -o /dev/null "$@" | sed -e "s/$CR\$//")
+
+if [[ "$pattern" == !* ]]
+then
+    if echo "$headers" | grep -qiE "^${pattern:1}"
+    then
+        echo -e "${RED}Failed!!!${NC}"
+        echo "Found unexpected header matching '${pattern:1}':"
+        echo "$headers"
+    else
+        echo -e "${GREEN}Passed${NC}"
+    fi
+else
+    if echo "$headers" | grep -qiE "^$pattern"
+    then
+        echo -e "${GREEN}Passed${NC}"
+    else
+        echo -e "${RED}Failed!!!${NC}"
+        echo "Could not find header matching '$pattern':"
+        echo "$headers"
+    fi
+fi
@@ -0,0 +1,49 @@
+#!/bin/bash -ue
+
+# Usage: expect_no_spin.sh <connections>
+# Opens <connections> idle connections, which should be more than hypershare
+# has file descriptors for, then checks that it is not busy looping on accept
+# and that it accepts again once the connections are closed.
+
+connections="$1"
+
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+NC='\033[0m' # No Color
+
+pid=$(pgrep -f "hypershare -d $DIR")
+
+function cpu_ticks() {
+    # utime + stime, in clock ticks
+    awk '{ print $14 + $15 }' /proc/$pid/stat
+}
+
+fds=()
+for i in $(seq $connections)
+do
+    exec {fd}<>/dev/tcp/localhost/$PORT
+    fds+=($fd)
+done
+sleep 1
+
+before=$(cpu_ticks)
+sleep 2
+used=$(( $(cpu_ticks) - before ))
+
+for fd in ${fds[@]}
+do
+    exec {fd}>&-
+done
+sleep 1
+
+status=$(curl -s -o /dev/null -w "%{http_code}" --max-time 2 http://localhost:$PORT/ || true)
+
+# Spinning would use close to 2 seconds of CPU time
+if [ $used -lt $(( $(getconf CLK_TCK) / 2 )) ] && [ "$status" == "200" ]
+then
+    echo -e "${GREEN}Passed${NC}"
+else
+    echo -e "${RED}Failed!!!${NC}"
+    echo "Used $used ticks of CPU time while out of file descriptors"
+    echo "Got status $status after closing connections"
+fi
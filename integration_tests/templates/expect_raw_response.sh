@@ -0,0 +1,38 @@
+#!/bin/bash -ue
+
+# Usage: expect_raw_response.sh <status> <close|open> <request>
+# Sends <request> (a printf format string) to hypershare as-is, then checks the
+# status of the first response and whether hypershare closed the connection.
+
+expected_status="$1"
+expected_connection="$2"
+request="$3"
+
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+NC='\033[0m' # No Color
+
+set +e
+response=$(printf "$request" | timeout 2 nc -t localhost $PORT)
+rc=$?
+set -e
+
+status_line=$(echo "$response" | head -n1)
+if [[ $rc == 124 ]]
+then
+    connection="open"
+else
+    connection="close"
+fi
+
+if [[ "$status_line" != *" $expected_status "* ]]
+then
+    echo -e "${RED}Failed!!!${NC}"
+    echo "Expected status $expected_status, got: $status_line"
+elif [[ "$connection" != "$expected_connection" ]]
+then
+    echo -e "${RED}Failed!!!${NC}"
+    echo "Expected connection to be $expected_connection, but it was $connection"
+else
+    echo -e "${GREEN}Passed${NC}"
+fi
@@ -0,0 +1,20 @@
+#!/bin/bash -ue
+
+# Usage: expect_status.sh <status> <curl arguments...>
+
+expected_status="$1"
+shift
+
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+NC='\033[0m' # No Color
+
+status=$(curl -s -o /dev/null -w "%{http_code}" "$@")
+
+if [[ "$status" == "$expected_status" ]]
+then
+    echo -e "${GREEN}Passed${NC}"
+else
+    echo -e "${RED}Failed!!!${NC}"
+    echo "Expected status $expected_status, got $status"
+fi
@@ -0,0 +1,55 @@
+#!/bin/bash -ue
+
+file="$1"
+# Status we expect hypershare to respond with. Anything other than 201 means
+# the upload should not have been stored.
+expected_status="${2:-201}"
+
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+NC='\033[0m' # No Color
+
+# Raw uploads are stored at the path they are sent to.
+mkdir -p $DIR/raw-upload
+output_file="raw-upload/$file"
+
+pushd $DIR > /dev/null
+
+status=$(curl -s -o /dev/null -w "%{http_code}" \
+    -H "Content-Type: application/octet-stream" \
+    --data-binary "@$file" \
+    "http://localhost:$PORT/raw-upload/$(echo -n "$file" | sed -e 's/ /%20/g')")
+
+popd > /dev/null
+
+if [[ "$status" != "$expected_status" ]]
+then
+    echo -e "${RED}Failed!!!${NC}"
+    echo "Expected status $expected_status, got $status"
+elif [[ "$expected_status" != "201" ]]
+then
+    if [ -e "$DIR/$output_file" ]
+    then
+        echo -e "${RED}Failed!!!${NC}"
+        echo "Rejected upload was stored anyway"
+        rm "$DIR/$output_file"
+    else
+        echo -e "${GREEN}Passed${NC}"
+    fi
+else
+    res="$(md5sum "$DIR/$file" "$DIR/$output_file" | awk '{ print $1 }')"
+
+    res1=$(echo $res | awk '{ print $1 }')
+    res2=$(echo $res | awk '{ print $2 }')
+
+    if [[ "$res1" ==  "$res2" ]]
+    then
+        echo -e "${GREEN}Passed${NC}"
+    else
+        echo -e "${RED}Failed!!!${NC}"
+        echo "Source: $res1"
+        echo "Output: $res2"
+    fi
+
+    rm "$DIR/$output_file"
+fi
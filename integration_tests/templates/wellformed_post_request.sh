@@ -12,12 +12,19 @@ NC='\033[0m' # No Color
 
 CR=$(echo -ne '\r')
 
+# The form around the file, plus the file itself
+length=$(( $(printf -- '--%s\r\nContent-Disposition: form-data; filename="%s"\r\n\r\n' \
+               "$BOUNDARY" "$output_file" | wc -c) \
+           + $(stat -c %s "$DIR/$file") \
+           + $(printf -- '\r\n--%s--' "$BOUNDARY" | wc -c) ))
+
 (
 cat - "$DIR/$file" <(echo -en "\r\n--$BOUNDARY--") << EOF
 POST / HTTP/1.0$CR
 Host: localhost$CR
 Connection: close$CR
 Content-Type: multpart/form-data;boundary="$BOUNDARY"$CR
+Content-Length: $length$CR
 $CR
 --$BOUNDARY$CR
 Content-Disposition: form-data; filename="$output_file"$CR
@@ -12,6 +12,12 @@ NC='\033[0m' # No Color
 
 CR=$(echo -ne '\r')
 
+# The form around the file, plus the file itself
+length=$(( $(printf -- '--%s\r\nContent-Disposition: form-data; filename="%s"\r\n\r\n' \
+               "$BOUNDARY" "$output_file" | wc -c) \
+           + $(stat -c %s "$DIR/$file") \
+           + $(printf -- '\r\n--%s--' "$BOUNDARY" | wc -c) ))
+
 resp=`
 (
 cat - <(sleep 1 && cat "$DIR/$file") <(echo -en "\r\n--$BOUNDARY--") << EOF
@@ -19,6 +25,7 @@ POST / HTTP/1.1$CR
 Host: localhost$CR
 Connection: close$CR
 Content-Type: multpart/form-data;boundary="$BOUNDARY"$CR
+Content-Length: $length$CR
 Expect: 100-continue$CR
 $CR
 --$BOUNDARY$CR
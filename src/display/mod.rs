@@ -1,6 +1,6 @@
 pub mod types;
 
-use crate::opts::types::Opts;
+use crate::{http::control::ControlCommand, opts::types::Opts};
 
 use types::{Connection, ConnectionSet, ControlEvent};
 
@@ -90,6 +90,7 @@ pub fn display(
     rx: mpsc::Receiver<ControlEvent>,
     needs_update: &AtomicBool,
     write_end: RawFd,
+    disabled: Arc<AtomicBool>,
     opts: &Opts,
 ) -> Result<(), io::Error> {
     let stdout = io::stdout().into_raw_mode()?;
@@ -97,8 +98,6 @@ pub fn display(
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let mut enabled = !opts.start_disabled;
-
     'outer: loop {
         // Print that the connection has been established
         {
@@ -166,7 +165,7 @@ pub fn display(
                     )))]),
                     ListItem::new(vec![Spans::from(Span::raw(format!(
                         "Status: {}",
-                        if enabled {
+                        if !disabled.load(Ordering::Acquire) {
                             "Serving requests"
                         } else {
                             "Rejecting requests"
@@ -195,11 +194,13 @@ pub fn display(
                     break 'outer;
                 }
                 Ok(ControlEvent::Toggle) => {
-                    let _ = unistd::write(write_end, b"t");
-                    enabled = !enabled;
+                    let _ = ControlCommand::Toggle.send(write_end);
                 }
                 Ok(ControlEvent::CloseAll) => {
-                    let _ = unistd::write(write_end, b"k");
+                    let _ = ControlCommand::CloseAll.send(write_end);
+                }
+                Ok(ControlEvent::Reload) => {
+                    let _ = ControlCommand::Reload.send(write_end);
                 }
                 Err(mpsc::TryRecvError::Empty) => {
                     break;
@@ -217,7 +218,7 @@ pub fn display(
         needs_update.store(true, Ordering::Release);
 
         // Poke `select` to give us more information.
-        let _ = unistd::write(write_end, b"p");
+        let _ = ControlCommand::Poke.send(write_end);
     }
 
     let _ = unistd::close(write_end);
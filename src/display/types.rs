@@ -179,7 +179,7 @@ impl ConnectionSet {
     pub fn update(&mut self, current_conns: &HashMap<i32, HttpConnection>) {
         let mut reindexed = HashMap::<SocketAddr, &HttpConnection>::new();
         for (_, conn) in current_conns {
-            let peer_addr = match conn.stream.peer_addr() {
+            let peer_addr = match conn.peer_addr() {
                 Ok(addr) => addr,
                 Err(_) => {
                     continue;
@@ -212,4 +212,5 @@ pub enum ControlEvent {
     Quit,
     Toggle,
     CloseAll,
+    Reload,
 }
@@ -0,0 +1,213 @@
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+use std::{
+    io::{self, Read, Write},
+    os::unix::io::{AsRawFd, RawFd},
+    path::Path,
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::mpsc,
+    thread,
+};
+
+use super::http_core::{status_from_code, HttpStatus};
+
+// Scripts that write more than this are answered with a 500
+const MAX_OUTPUT_SIZE: usize = 64 * 1024 * 1024;
+
+// A running CGI script. Its output is read as it becomes available, from the
+// main loop, and the request body (if any) is fed to it from a separate
+// thread, so a slow script never holds up other connections.
+pub struct CgiProcess {
+    child: Child,
+    stdout: ChildStdout,
+    output: Vec<u8>,
+    // Request body on its way to the script's stdin. Dropping this closes it.
+    body_sender: Option<mpsc::Sender<Vec<u8>>>,
+    // Bytes of the request body that have yet to be received
+    pub body_remaining: usize,
+}
+
+pub struct CgiOutput {
+    pub status: HttpStatus,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl CgiProcess {
+    // Start `script` in its own directory. `body_length` bytes of request body
+    // are expected through `send_body`.
+    pub fn spawn(
+        script: &Path,
+        env: Vec<(String, String)>,
+        body_length: usize,
+    ) -> Result<CgiProcess, io::Error> {
+        let mut command = Command::new(script);
+        command
+            .env_clear()
+            .envs(env)
+            .stdin(if body_length > 0 {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        if let Some(dir) = script.parent() {
+            command.current_dir(dir);
+        }
+        if let Ok(path) = std::env::var("PATH") {
+            command.env("PATH", path);
+        }
+        let mut child = command.spawn()?;
+
+        let stdout = child.stdout.take().unwrap();
+        fcntl(stdout.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .map_err(io::Error::other)?;
+
+        let body_sender = child.stdin.take().map(|mut stdin| {
+            let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+            thread::spawn(move || {
+                for chunk in receiver {
+                    // A script is free to exit without reading its input
+                    if stdin.write_all(&chunk).is_err() {
+                        break;
+                    }
+                }
+            });
+            sender
+        });
+
+        Ok(CgiProcess {
+            child,
+            stdout,
+            output: Vec::new(),
+            body_sender,
+            body_remaining: body_length,
+        })
+    }
+
+    pub fn get_fd(&self) -> RawFd { self.stdout.as_raw_fd() }
+
+    // Pass on part of the request body. Anything past the declared length is
+    // dropped.
+    pub fn send_body(&mut self, data: &[u8]) {
+        let data = &data[..data.len().min(self.body_remaining)];
+        self.body_remaining -= data.len();
+        if let Some(sender) = &self.body_sender {
+            let _ = sender.send(data.to_vec());
+        }
+        if self.body_remaining == 0 {
+            self.body_sender = None;
+        }
+    }
+
+    // Read whatever output is available, returning true once the script has
+    // closed its output.
+    pub fn read_output(&mut self) -> Result<bool, io::Error> {
+        let mut buf = [0; 64 * 1024];
+        loop {
+            match self.stdout.read(&mut buf) {
+                Ok(0) => {
+                    return Ok(true);
+                }
+                Ok(amt) => {
+                    if self.output.len() + amt > MAX_OUTPUT_SIZE {
+                        return Err(io::Error::other("script output is too large"));
+                    }
+                    self.output.extend_from_slice(&buf[..amt]);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(false);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> { std::mem::take(&mut self.output) }
+}
+
+impl Drop for CgiProcess {
+    // Scripts never outlive their connection
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Name of the environment variable for a request header, as in RFC 3875:
+// "Accept-Language" becomes HTTP_ACCEPT_LANGUAGE.
+pub fn header_variable(name: &str) -> String {
+    format!("HTTP_{}", name.to_uppercase().replace('-', "_"))
+}
+
+// Split what a script wrote into its headers and body. The script picks the
+// status with a Status header, and a Location on its own means a redirect.
+pub fn parse_output(output: &[u8]) -> Result<CgiOutput, String> {
+    let (head_end, body_start) = match find_blank_line(output) {
+        Some(ends) => ends,
+        None => {
+            return Err("The script did not finish its headers.".to_string());
+        }
+    };
+    let head = String::from_utf8_lossy(&output[..head_end]);
+
+    let mut status = None;
+    let mut headers = Vec::new();
+    for line in head.lines() {
+        let colon = match line.find(':') {
+            Some(idx) => idx,
+            None => {
+                return Err("The script wrote a malformed header.".to_string());
+            }
+        };
+        let name = line[..colon].trim();
+        let value = line[colon + 1..].trim();
+        if name.eq_ignore_ascii_case("status") {
+            let code = value.split(' ').next().unwrap_or("");
+            status = match code.parse().ok().and_then(status_from_code) {
+                Some(status) => Some(status),
+                None => {
+                    return Err("The script returned an unsupported status.".to_string());
+                }
+            };
+        } else {
+            headers.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    let redirect = headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("location"));
+    let status = match status {
+        Some(status) => status,
+        None if redirect => HttpStatus::Found,
+        None => HttpStatus::OK,
+    };
+
+    Ok(CgiOutput {
+        status,
+        headers,
+        body: output[body_start..].to_vec(),
+    })
+}
+
+// Where the headers end and the body starts. Scripts may end lines with
+// either "\r\n" or "\n".
+fn find_blank_line(output: &[u8]) -> Option<(usize, usize)> {
+    for i in 0..output.len() {
+        if output[i..].starts_with(b"\r\n\r\n") {
+            return Some((i, i + 4));
+        }
+        if output[i..].starts_with(b"\n\n") {
+            return Some((i, i + 2));
+        }
+        if output[i..].starts_with(b"\n\r\n") {
+            return Some((i, i + 3));
+        }
+    }
+    None
+}
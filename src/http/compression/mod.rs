@@ -0,0 +1,214 @@
+use flate2::{write::GzEncoder, Compression};
+
+use crate::http::http_core::parse_qvalue_list;
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// Responses are compressed in memory before any of them is sent, and nothing
+// else is served in the meantime, so larger ones are sent as they are.
+// Precompressed copies have no limit.
+const MAX_SIZE: usize = 1024 * 1024;
+
+// Brotli quality ranges from 0 to 11. The highest levels are far too slow to
+// run on every request.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Identity,
+}
+
+pub fn encoding_to_str(encoding: Encoding) -> &'static str {
+    match encoding {
+        Encoding::Brotli => "br",
+        Encoding::Gzip => "gzip",
+        Encoding::Identity => "identity",
+    }
+}
+
+// Preference used to break ties between equal q-values
+fn rank(encoding: Encoding) -> u8 {
+    match encoding {
+        Encoding::Brotli => 2,
+        Encoding::Gzip => 1,
+        Encoding::Identity => 0,
+    }
+}
+
+// Pick the encoding the client likes best from an Accept-Encoding header
+// (e.g. "gzip;q=0.5, br"), out of those `available` for this response. None
+// means the client accepts none of them, not even identity.
+pub fn choose_encoding(accept_encoding: Option<&str>, available: &[Encoding]) -> Option<Encoding> {
+    let header = match accept_encoding {
+        Some(header) => header,
+        None => {
+            return Some(Encoding::Identity);
+        }
+    };
+
+    let qvalues = parse_qvalue_list(header);
+    let lookup = |name: &str| qvalues.iter().find(|(n, _)| n == name).map(|(_, q)| *q);
+    let wildcard = lookup("*");
+    let qvalue = |encoding: Encoding| match encoding {
+        Encoding::Brotli => lookup("br").or(wildcard).unwrap_or(0),
+        Encoding::Gzip => lookup("gzip")
+            .or_else(|| lookup("x-gzip"))
+            .or(wildcard)
+            .unwrap_or(0),
+        // Identity is always acceptable unless refused, but only as a last
+        // resort when not listed.
+        Encoding::Identity => lookup("identity").or(wildcard).unwrap_or(1),
+    };
+
+    available
+        .iter()
+        .map(|encoding| (qvalue(*encoding), rank(*encoding), *encoding))
+        .filter(|(q, _, _)| *q > 0)
+        .max_by_key(|(q, rank, _)| (*q, *rank))
+        .map(|(_, _, encoding)| encoding)
+}
+
+// Whether a response of this type usually gets smaller when compressed. Images,
+// video, and archives are already compressed.
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(";").next().unwrap_or("").trim();
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "image/svg+xml"
+                | "application/wasm"
+        )
+}
+
+// Whether a response of this type and length should be compressed, given the
+// configured minimum length.
+pub fn should_compress(content_type: &str, length: usize, min_size: usize) -> bool {
+    is_compressible(content_type) && length >= min_size && length <= MAX_SIZE
+}
+
+pub fn compress<R>(encoding: Encoding, mut body: R) -> Result<Vec<u8>, io::Error>
+where
+    R: io::Read,
+{
+    match encoding {
+        Encoding::Brotli => {
+            let mut encoder =
+                brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+            io::copy(&mut body, &mut encoder)?;
+            Ok(encoder.into_inner())
+        }
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            io::copy(&mut body, &mut encoder)?;
+            encoder.finish()
+        }
+        Encoding::Identity => {
+            let mut out = Vec::new();
+            io::copy(&mut body, &mut out)?;
+            Ok(out)
+        }
+    }
+}
+
+fn sidecar_path(path: &Path, encoding: Encoding) -> Option<PathBuf> {
+    let extension = match encoding {
+        Encoding::Brotli => "br",
+        Encoding::Gzip => "gz",
+        Encoding::Identity => {
+            return None;
+        }
+    };
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(extension);
+    Some(PathBuf::from(sidecar))
+}
+
+// A precompressed copy of `path` (like `app.js.br` next to `app.js`), if one
+// exists for this encoding. A copy older than the original is stale, and is
+// ignored.
+pub fn find_sidecar(path: &Path, encoding: Encoding) -> Option<PathBuf> {
+    let sidecar = sidecar_path(path, encoding)?;
+    let metadata = fs::metadata(&sidecar).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    match (
+        metadata.modified(),
+        fs::metadata(path).and_then(|m| m.modified()),
+    ) {
+        (Ok(sidecar_time), Ok(original_time)) if sidecar_time < original_time => None,
+        _ => Some(sidecar),
+    }
+}
+
+// Whether `path` has a precompressed copy in any encoding
+pub fn has_sidecar(path: &Path) -> bool {
+    find_sidecar(path, Encoding::Brotli).is_some() || find_sidecar(path, Encoding::Gzip).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_encoding_qvalues() {
+        let all = [Encoding::Identity, Encoding::Brotli, Encoding::Gzip];
+        let identity = [Encoding::Identity];
+
+        assert_eq!(choose_encoding(None, &all), Some(Encoding::Identity));
+        assert_eq!(choose_encoding(Some("gzip"), &all), Some(Encoding::Gzip));
+        // gzip is refused outright
+        assert_eq!(
+            choose_encoding(Some("gzip;q=0, br"), &all),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(
+            choose_encoding(Some("gzip;q=0, br;q=0"), &all),
+            Some(Encoding::Identity)
+        );
+        assert_eq!(
+            choose_encoding(Some("br;q=0.2, gzip;q=0.8"), &all),
+            Some(Encoding::Gzip)
+        );
+        // Anything but identity
+        assert_eq!(choose_encoding(Some("*;q=0"), &all), None);
+        assert_eq!(
+            choose_encoding(Some("gzip, *;q=0"), &all),
+            Some(Encoding::Gzip)
+        );
+        // Identity is refused, so only a compressed response will do
+        assert_eq!(choose_encoding(Some("identity;q=0"), &all), None);
+        assert_eq!(
+            choose_encoding(Some("gzip, identity;q=0"), &all),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(choose_encoding(Some("gzip, identity;q=0"), &identity), None);
+        assert_eq!(
+            choose_encoding(Some("gzip, br"), &identity),
+            Some(Encoding::Identity)
+        );
+    }
+
+    #[test]
+    fn compression_decision() {
+        // Too small to be worth it
+        assert!(!should_compress("text/plain; charset=utf-8", 100, 1024));
+        assert!(should_compress("text/plain; charset=utf-8", 4096, 1024));
+        assert!(should_compress("image/svg+xml", 4096, 1024));
+        // Already compressed
+        assert!(!should_compress("image/jpeg", 4096, 1024));
+        assert!(!should_compress("application/zip", 4096, 1024));
+        // Too large to hold up other clients for
+        assert!(!should_compress("text/plain", 2 * 1024 * 1024, 1024));
+    }
+}
@@ -0,0 +1,74 @@
+use nix::unistd;
+
+use std::os::unix::io::RawFd;
+
+// Commands sent to `HttpTui::run` over its control pipe, one byte each.
+// Closing the pipe stops the server.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ControlCommand {
+    // Enable or disable the server
+    Toggle,
+    // Close every connection, finished or not
+    CloseAll,
+    // Do nothing but wake the loop, so that its callback runs again
+    Poke,
+    // Apply options from the reloader given to `HttpTui::set_reloader`
+    Reload,
+}
+
+impl ControlCommand {
+    pub fn from_byte(byte: u8) -> Option<ControlCommand> {
+        match byte {
+            b't' => Some(ControlCommand::Toggle),
+            b'k' => Some(ControlCommand::CloseAll),
+            b'p' => Some(ControlCommand::Poke),
+            b'r' => Some(ControlCommand::Reload),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            ControlCommand::Toggle => b't',
+            ControlCommand::CloseAll => b'k',
+            ControlCommand::Poke => b'p',
+            ControlCommand::Reload => b'r',
+        }
+    }
+
+    pub fn send(self, pipe_write: RawFd) -> nix::Result<usize> {
+        unistd::write(pipe_write, &[self.to_byte()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(
+            ControlCommand::from_byte(b't'),
+            Some(ControlCommand::Toggle)
+        );
+        assert_eq!(
+            ControlCommand::from_byte(b'k'),
+            Some(ControlCommand::CloseAll)
+        );
+        assert_eq!(ControlCommand::from_byte(b'p'), Some(ControlCommand::Poke));
+        assert_eq!(
+            ControlCommand::from_byte(b'r'),
+            Some(ControlCommand::Reload)
+        );
+        assert_eq!(ControlCommand::from_byte(b'x'), None);
+        assert_eq!(ControlCommand::from_byte(0), None);
+        for command in &[
+            ControlCommand::Toggle,
+            ControlCommand::CloseAll,
+            ControlCommand::Poke,
+            ControlCommand::Reload,
+        ] {
+            assert_eq!(ControlCommand::from_byte(command.to_byte()), Some(*command));
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use regex::Regex;
+
+use super::http_core::escape_json;
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// Counts completed downloads of each file, keyed by its canonical path within
+// the root it is in, so that every name a file can be fetched by adds to the
+// same count. With a file to save to, the counts are written out after every
+// download, and loaded from it at startup.
+pub struct DownloadCounter {
+    // Ordered so that the saved file and /_downloads are stable
+    counts: BTreeMap<String, u64>,
+    file: Option<PathBuf>,
+    // Canonical, in the order they are searched
+    roots: Vec<PathBuf>,
+}
+
+impl DownloadCounter {
+    pub fn new(file: Option<&Path>, roots: Vec<PathBuf>) -> Result<DownloadCounter, io::Error> {
+        let counts = match file {
+            Some(file) => match fs::read_to_string(file) {
+                Ok(json) => parse_counts(&json),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => {
+                    return Err(e);
+                }
+            },
+            None => BTreeMap::new(),
+        };
+        Ok(DownloadCounter {
+            counts: counts,
+            file: file.map(|f| f.to_path_buf()),
+            roots,
+        })
+    }
+
+    // Count a download of the file at `canonical`, saving the counts if there
+    // is somewhere to save them.
+    pub fn increment(&mut self, canonical: &Path) -> Result<(), io::Error> {
+        *self.counts.entry(self.key(canonical)).or_insert(0) += 1;
+        self.save()
+    }
+
+    pub fn count(&self, canonical: &Path) -> u64 {
+        self.counts.get(&self.key(canonical)).copied().unwrap_or(0)
+    }
+
+    // The path from the first root the file is in, like `/sub/a.txt`. A file
+    // reached through a link out of every root has only its full path.
+    fn key(&self, canonical: &Path) -> String {
+        match self
+            .roots
+            .iter()
+            .find_map(|root| canonical.strip_prefix(root).ok())
+        {
+            Some(relative) => format!("/{}", relative.to_string_lossy()),
+            None => canonical.to_string_lossy().into_owned(),
+        }
+    }
+
+    fn save(&self) -> Result<(), io::Error> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => {
+                return Ok(());
+            }
+        };
+        // Write a copy and move it into place, so a crash while writing
+        // cannot lose the old counts.
+        let mut temp = file.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, self.to_json())?;
+        fs::rename(&temp, file)
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(path, count)| format!("  \"{}\": {}", escape_json(path), count))
+            .collect();
+        if entries.is_empty() {
+            return "{}\n".to_string();
+        }
+        format!("{{\n{}\n}}\n", entries.join(",\n"))
+    }
+}
+
+fn unescape_json(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            't' => unescaped.push('\t'),
+            'b' => unescaped.push('\u{8}'),
+            'f' => unescaped.push('\u{c}'),
+            'u' => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let code = u32::from_str_radix(&hex, 16).ok()?;
+                unescaped.push(std::char::from_u32(code)?);
+            }
+            c => unescaped.push(c),
+        }
+    }
+    Some(unescaped)
+}
+
+// Read back what `to_json` wrote: a single object of paths to counts.
+// Entries that cannot be read are skipped.
+fn parse_counts(json: &str) -> BTreeMap<String, u64> {
+    lazy_static! {
+        static ref ENTRY: Regex = Regex::new(r#""((?:[^"\\]|\\.)*)"\s*:\s*(\d+)"#).unwrap();
+    };
+
+    let mut counts = BTreeMap::new();
+    for cap in ENTRY.captures_iter(json) {
+        if let (Some(path), Ok(count)) = (unescape_json(&cap[1]), cap[2].parse::<u64>()) {
+            counts.insert(path, count);
+        }
+    }
+    counts
+}
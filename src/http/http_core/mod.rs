@@ -1,9 +1,12 @@
 extern crate regex;
 use regex::{Captures, Regex};
 
-use std::{boxed::Box, cmp::min, io, net::TcpStream};
-
-use std::io::Write;
+use std::{
+    boxed::Box,
+    cmp::min,
+    io,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 pub mod types;
 use types::ResponseDataType;
@@ -13,6 +16,45 @@ pub enum HttpMethod {
     GET,
     HEAD,
     POST,
+    OPTIONS,
+}
+
+pub fn method_to_str(m: &HttpMethod) -> &'static str {
+    match m {
+        HttpMethod::GET => "GET",
+        HttpMethod::HEAD => "HEAD",
+        HttpMethod::POST => "POST",
+        HttpMethod::OPTIONS => "OPTIONS",
+    }
+}
+
+pub fn method_from_str(s: &str) -> Option<HttpMethod> {
+    match s {
+        "GET" => Some(HttpMethod::GET),
+        "HEAD" => Some(HttpMethod::HEAD),
+        "POST" => Some(HttpMethod::POST),
+        "OPTIONS" => Some(HttpMethod::OPTIONS),
+        _ => None,
+    }
+}
+
+// Real methods are far shorter than this, so a longer one is rejected without
+// waiting for the rest of the request.
+const MAX_METHOD_LENGTH: usize = 16;
+
+pub fn is_token_char(c: u8) -> bool { c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c) }
+
+// Check the method at the start of `line`, which may be a request line that
+// has only partly been received.
+pub fn check_method_token(line: &[u8]) -> Result<(), HttpStatus> {
+    let method = match line.iter().position(|&c| c == b' ') {
+        Some(end) => &line[..end],
+        None => line,
+    };
+    if method.len() > MAX_METHOD_LENGTH || !method.iter().all(|&c| is_token_char(c)) {
+        return Err(HttpStatus::BadRequest);
+    }
+    Ok(())
 }
 
 #[derive(PartialEq, Clone)]
@@ -35,18 +77,32 @@ pub enum HttpStatus {
     Continue,                // 100
     OK,                      // 200
     Created,                 // 201
+    NoContent,               // 204
     MovedPermanently,        // 301
+    Found,                   // 302
+    NotModified,             // 304
     PartialContent,          // 206
-    BadRequest,              // 401
+    BadRequest,              // 400
+    Unauthorized,            // 401
     PermissionDenied,        // 403
     NotFound,                // 404
     MethodNotAllowed,        // 405
+    NotAcceptable,           // 406
+    Conflict,                // 409
+    LengthRequired,          // 411
+    PreconditionFailed,      // 412
     PayloadTooLarge,         // 413
+    UriTooLong,              // 414
+    UnsupportedMediaType,    // 415
+    ExpectationFailed,       // 417
     UnprocessableEntity,     // 422
+    Locked,                  // 423
     RequestHeadersTooLarge,  // 431
     ServerError,             // 500
     NotImplemented,          // 501
+    BadGateway,              // 502
     ServiceUnavailable,      // 503
+    GatewayTimeout,          // 504
     HttpVersionNotSupported, // 505
 }
 
@@ -55,39 +111,127 @@ pub fn status_to_code(status: &HttpStatus) -> u16 {
         HttpStatus::Continue => 100,
         HttpStatus::OK => 200,
         HttpStatus::Created => 201,
+        HttpStatus::NoContent => 204,
         HttpStatus::MovedPermanently => 301,
+        HttpStatus::Found => 302,
+        HttpStatus::NotModified => 304,
         HttpStatus::PartialContent => 206,
-        HttpStatus::BadRequest => 401,
+        HttpStatus::BadRequest => 400,
+        HttpStatus::Unauthorized => 401,
         HttpStatus::PermissionDenied => 403,
         HttpStatus::NotFound => 404,
         HttpStatus::MethodNotAllowed => 405,
+        HttpStatus::NotAcceptable => 406,
+        HttpStatus::Conflict => 409,
+        HttpStatus::LengthRequired => 411,
+        HttpStatus::PreconditionFailed => 412,
         HttpStatus::PayloadTooLarge => 413,
+        HttpStatus::UriTooLong => 414,
+        HttpStatus::UnsupportedMediaType => 415,
+        HttpStatus::ExpectationFailed => 417,
         HttpStatus::UnprocessableEntity => 422,
+        HttpStatus::Locked => 423,
         HttpStatus::RequestHeadersTooLarge => 431,
         HttpStatus::ServerError => 500,
         HttpStatus::NotImplemented => 501,
+        HttpStatus::BadGateway => 502,
         HttpStatus::ServiceUnavailable => 503,
+        HttpStatus::GatewayTimeout => 504,
         HttpStatus::HttpVersionNotSupported => 505,
     }
 }
 
+// The inverse of `status_to_code`, for the statuses this server knows
+pub fn status_from_code(code: u16) -> Option<HttpStatus> {
+    match code {
+        100 => Some(HttpStatus::Continue),
+        200 => Some(HttpStatus::OK),
+        201 => Some(HttpStatus::Created),
+        204 => Some(HttpStatus::NoContent),
+        206 => Some(HttpStatus::PartialContent),
+        301 => Some(HttpStatus::MovedPermanently),
+        302 => Some(HttpStatus::Found),
+        304 => Some(HttpStatus::NotModified),
+        400 => Some(HttpStatus::BadRequest),
+        401 => Some(HttpStatus::Unauthorized),
+        403 => Some(HttpStatus::PermissionDenied),
+        404 => Some(HttpStatus::NotFound),
+        405 => Some(HttpStatus::MethodNotAllowed),
+        406 => Some(HttpStatus::NotAcceptable),
+        409 => Some(HttpStatus::Conflict),
+        411 => Some(HttpStatus::LengthRequired),
+        412 => Some(HttpStatus::PreconditionFailed),
+        413 => Some(HttpStatus::PayloadTooLarge),
+        414 => Some(HttpStatus::UriTooLong),
+        415 => Some(HttpStatus::UnsupportedMediaType),
+        417 => Some(HttpStatus::ExpectationFailed),
+        422 => Some(HttpStatus::UnprocessableEntity),
+        423 => Some(HttpStatus::Locked),
+        431 => Some(HttpStatus::RequestHeadersTooLarge),
+        500 => Some(HttpStatus::ServerError),
+        501 => Some(HttpStatus::NotImplemented),
+        502 => Some(HttpStatus::BadGateway),
+        503 => Some(HttpStatus::ServiceUnavailable),
+        504 => Some(HttpStatus::GatewayTimeout),
+        505 => Some(HttpStatus::HttpVersionNotSupported),
+        _ => None,
+    }
+}
+
+// Whether an error response with this status must close the connection. These
+// mean the request could not be parsed or framed, so there is no telling where
+// the next one would start. Other errors leave the connection as the client
+// asked.
+pub fn status_closes_connection(status: &HttpStatus) -> bool {
+    matches!(
+        status,
+        HttpStatus::BadRequest
+            | HttpStatus::PayloadTooLarge
+            | HttpStatus::RequestHeadersTooLarge
+            | HttpStatus::HttpVersionNotSupported
+    )
+}
+
+// Whether responses with this status never have a body, so that they end
+// with their headers and must not give a length for one
+pub fn status_forbids_body(status: &HttpStatus) -> bool {
+    matches!(
+        status,
+        HttpStatus::Continue | HttpStatus::NoContent | HttpStatus::NotModified
+    )
+}
+
 pub fn status_to_message(status: &HttpStatus) -> &'static str {
     match status {
         HttpStatus::Continue => "Continue",
         HttpStatus::OK => "OK",
         HttpStatus::Created => "Created",
+        HttpStatus::NoContent => "No content",
         HttpStatus::MovedPermanently => "Moved permanently",
+        HttpStatus::Found => "Found",
+        HttpStatus::NotModified => "Not modified",
         HttpStatus::PartialContent => "Partial content",
         HttpStatus::BadRequest => "Bad request",
+        HttpStatus::Unauthorized => "Unauthorized",
         HttpStatus::PermissionDenied => "Permission denied",
         HttpStatus::NotFound => "Not found",
         HttpStatus::MethodNotAllowed => "Method not allowed",
+        HttpStatus::NotAcceptable => "Not acceptable",
+        HttpStatus::Conflict => "Conflict",
+        HttpStatus::LengthRequired => "Length required",
+        HttpStatus::PreconditionFailed => "Precondition failed",
         HttpStatus::PayloadTooLarge => "Payload too large",
+        HttpStatus::UriTooLong => "URI too long",
+        HttpStatus::UnsupportedMediaType => "Unsupported media type",
+        HttpStatus::ExpectationFailed => "Expectation failed",
         HttpStatus::UnprocessableEntity => "Unprocessable entity",
+        HttpStatus::Locked => "Locked",
         HttpStatus::RequestHeadersTooLarge => "Request header fields too large",
         HttpStatus::ServerError => "Server error",
         HttpStatus::NotImplemented => "Method not implemented",
+        HttpStatus::BadGateway => "Bad gateway",
         HttpStatus::ServiceUnavailable => "Service unavailable",
+        HttpStatus::GatewayTimeout => "Gateway timeout",
         HttpStatus::HttpVersionNotSupported => "HTTP version not supported",
     }
 }
@@ -97,11 +241,13 @@ pub struct HttpHeader {
     value: String,
 }
 
-// Don't support multiple header values yet
+// In the order they were given. A name may appear more than once.
 type HttpHeaderSet = Vec<HttpHeader>;
 
 pub struct HttpRequest {
     pub path: String,
+    // Everything after the '?', if there was one
+    pub query: Option<String>,
     pub method: Option<HttpMethod>,
     pub version: HttpVersion,
     headers: HttpHeaderSet,
@@ -114,13 +260,20 @@ impl HttpRequest {
          *
          */
         let lines: Vec<&str> = request_str.split("\r\n").collect();
+        // A lone CR or LF would start a new line in whatever the target or
+        // a header is passed on to, like a proxied request, and a NUL would
+        // cut it short.
+        if lines.iter().any(|line| line.contains(['\r', '\n', '\0'])) {
+            return Err(HttpStatus::BadRequest);
+        }
         // We know that lines will always be at least 2 lines long.
         let first: Vec<&str> = lines[0].split(" ").collect();
         if first.len() != 3 {
             return Err(HttpStatus::BadRequest);
         }
         let verb = first[0];
-        let path = ignore_get_params(first[1]);
+        check_method_token(verb.as_bytes())?;
+        let (path, query) = split_query(first[1]);
         let version_str = first[2];
 
         let version = if version_str == "HTTP/1.0" {
@@ -137,47 +290,94 @@ impl HttpRequest {
             return Err(HttpStatus::RequestHeadersTooLarge);
         }
 
-        let method = if verb == "GET" {
-            Some(HttpMethod::GET)
-        } else if verb == "HEAD" {
-            Some(HttpMethod::HEAD)
-        } else if verb == "POST" {
-            Some(HttpMethod::POST)
-        } else {
-            None
-        };
+        let method = method_from_str(verb);
 
         let mut headers = HttpHeaderSet::new();
         for header_line in &lines[1..] {
             if header_line.len() == 0 {
                 continue;
             }
-            let keyval: Vec<&str> = header_line.split(":").collect();
-            if keyval.len() != 2 {
-                continue;
-            }
+            // Values may have colons of their own, as in "Host: a:8080"
+            let (key, value) = match header_line.split_once(':') {
+                Some(keyval) => keyval,
+                None => continue,
+            };
             headers.push(HttpHeader {
-                key: keyval[0].trim().to_lowercase(),
-                value: keyval[1].trim().to_string(),
+                key: key.trim().to_lowercase(),
+                value: value.trim().to_string(),
             });
         }
 
+        // With two, there is no telling which site the request is for
+        if headers.iter().filter(|header| header.key == "host").count() > 1 {
+            return Err(HttpStatus::BadRequest);
+        }
+
+        // Nor where the body ends, unless they agree
+        let mut lengths = headers
+            .iter()
+            .filter(|header| header.key == "content-length")
+            .map(|header| &header.value);
+        if let Some(first) = lengths.next() {
+            if lengths.any(|length| length != first) {
+                return Err(HttpStatus::BadRequest);
+            }
+        }
+
+        // Paths end at the first null as far as the filesystem is concerned
+        let path = undo_percent_encoding(path);
+        if path.contains('\0') {
+            return Err(HttpStatus::BadRequest);
+        }
+
         Ok(HttpRequest {
-            path: undo_percent_encoding(path),
+            path,
+            query: query.map(|q| q.to_string()),
             method: method,
             version: version,
             headers: headers,
         })
     }
 
+    pub fn get_query_param(&self, key: &str) -> Option<String> {
+        let query = self.query.as_ref()?;
+        for pair in query.split('&') {
+            let mut keyval = pair.splitn(2, '=');
+            if keyval.next() == Some(key) {
+                return Some(undo_percent_encoding(keyval.next().unwrap_or("")));
+            }
+        }
+        None
+    }
+
+    // Every header, with lowercased names
+    pub fn get_headers(&self) -> Vec<(&str, &str)> {
+        self.headers
+            .iter()
+            .map(|header| (&header.key[..], &header.value[..]))
+            .collect()
+    }
+
+    // The first value given for the header `key`, in any case
     pub fn get_header(&self, key: &str) -> Option<&String> {
         for header in &self.headers {
-            if header.key == key.to_string() {
+            if header.key.eq_ignore_ascii_case(key) {
                 return Some(&header.value);
             }
         }
         None
     }
+
+    // Every value given for the header `key`, in order. Lists split over
+    // several lines, like X-Forwarded-For, are whole again once joined with
+    // ", ".
+    pub fn get_header_all(&self, key: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|header| header.key.eq_ignore_ascii_case(key))
+            .map(|header| &header.value[..])
+            .collect()
+    }
 }
 
 fn get_byte_from_hex(tens_dig: u8, ones_dig: u8) -> u8 {
@@ -216,6 +416,7 @@ pub struct HttpResponse {
     data: ResponseDataType,
     buffer: Box<[u8]>,
     bytes_to_write: usize,
+    chunked: bool,
 }
 
 impl HttpResponse {
@@ -234,12 +435,21 @@ impl HttpResponse {
             },
             data: ResponseDataType::None,
             bytes_to_write: 0,
+            chunked: false,
         }
     }
 
     pub fn add_body(&mut self, data: ResponseDataType) { self.data = data; }
 
-    pub fn clear_body(&mut self) { self.data = ResponseDataType::None; }
+    pub fn clear_body(&mut self) {
+        self.data = ResponseDataType::None;
+        self.bytes_to_write = 0;
+    }
+
+    // Whether all of the body has been written
+    pub fn is_finished(&self) -> bool { self.bytes_to_write == 0 }
+
+    pub fn get_status(&self) -> HttpStatus { self.status }
 
     pub fn add_header(&mut self, key: String, value: String) {
         self.headers.push(HttpHeader {
@@ -256,9 +466,45 @@ impl HttpResponse {
         self.bytes_to_write = size;
     }
 
+    // The body will be sent as it becomes available with `write_chunk`,
+    // rather than having a known length.
+    pub fn set_chunked(&mut self) {
+        self.headers.push(HttpHeader {
+            key: "Transfer-Encoding".to_string(),
+            value: "chunked".to_string(),
+        });
+        self.chunked = true;
+    }
+
+    pub fn write_chunk<W>(&mut self, stream: &mut W, data: &[u8]) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        assert_eq!(self.headers_written, true);
+        if self.chunked {
+            stream.write_all(format!("{:x}\r\n", data.len()).as_bytes())?;
+            stream.write_all(data)?;
+            stream.write_all(b"\r\n")?;
+        } else {
+            // Without chunking, the body ends when the connection closes.
+            stream.write_all(data)?;
+        }
+        Ok(())
+    }
+
     pub fn get_code(&self) -> String { status_to_code(&self.status).to_string() }
 
-    pub fn write_headers_to_stream(&mut self, mut stream: &TcpStream) -> Result<(), io::Error> {
+    pub fn get_header(&self, key: &str) -> Option<&String> {
+        self.headers
+            .iter()
+            .find(|header| header.key.eq_ignore_ascii_case(key))
+            .map(|header| &header.value)
+    }
+
+    pub fn write_headers_to_stream<W>(&mut self, stream: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
         assert_eq!(self.headers_written, false);
         let code = status_to_code(&self.status);
         let message = status_to_message(&self.status);
@@ -271,7 +517,20 @@ impl HttpResponse {
 
         stream.write(leader.as_bytes())?;
 
+        // Whatever was added for a body is dropped along with it
+        let bodyless = status_forbids_body(&self.status);
+        if bodyless {
+            self.clear_body();
+            self.chunked = false;
+        }
+
         for header in &self.headers {
+            if bodyless
+                && (header.key.eq_ignore_ascii_case("content-length")
+                    || header.key.eq_ignore_ascii_case("transfer-encoding"))
+            {
+                continue;
+            }
             stream.write(format!("{}: {}\r\n", header.key, header.value).as_bytes())?;
         }
 
@@ -282,7 +541,10 @@ impl HttpResponse {
         Ok(())
     }
 
-    pub fn partial_write_to_stream(&mut self, stream: &TcpStream) -> Result<usize, io::Error> {
+    pub fn partial_write_to_stream<W>(&mut self, stream: &mut W) -> Result<usize, io::Error>
+    where
+        W: io::Write,
+    {
         assert_eq!(self.headers_written, true);
         let amt_written = match self.data {
             ResponseDataType::String(ref mut s) => generic_partial_write_to_stream(
@@ -297,6 +559,18 @@ impl HttpResponse {
                 fle,
                 stream,
             ),
+            ResponseDataType::Bytes(ref mut bytes) => generic_partial_write_to_stream(
+                self.bytes_to_write,
+                &mut self.buffer[..],
+                bytes,
+                stream,
+            ),
+            ResponseDataType::Ranges(ref mut ranges) => generic_partial_write_to_stream(
+                self.bytes_to_write,
+                &mut self.buffer[..],
+                ranges,
+                stream,
+            ),
             ResponseDataType::None => Ok(0),
         };
 
@@ -308,14 +582,15 @@ impl HttpResponse {
     }
 }
 
-fn generic_partial_write_to_stream<T>(
+fn generic_partial_write_to_stream<T, W>(
     bytes_to_write: usize,
     buffer: &mut [u8],
     body: &mut T,
-    mut stream: &TcpStream,
+    stream: &mut W,
 ) -> Result<usize, io::Error>
 where
     T: io::Seek + io::Read,
+    W: io::Write,
 {
     let write_length = min(bytes_to_write, BUFFER_SIZE);
     let amt_read = body.read(&mut buffer[..write_length])?;
@@ -323,11 +598,217 @@ where
         return Ok(0);
     }
     // HttpResponse::write_fully(&buffer[..amt_read], stream)?;
-    let amt_written = stream.write(&buffer[..amt_read])?;
+    let amt_written = match stream.write(&buffer[..amt_read]) {
+        Ok(amt) => amt,
+        Err(error) => {
+            // Nothing was taken, so all of it is read again next time
+            body.seek(io::SeekFrom::Current(-(amt_read as i64)))?;
+            return Err(error);
+        }
+    };
+    // Step back over whatever was read but not taken
     if amt_written < amt_read {
-        body.seek(io::SeekFrom::Current((amt_read - amt_written) as i64))?;
+        body.seek(io::SeekFrom::Current(-((amt_read - amt_written) as i64)))?;
     }
     Ok(amt_written)
 }
 
-fn ignore_get_params(path: &str) -> &str { return &path[..path.find('?').unwrap_or(path.len())]; }
+// Converts days since the epoch into a (year, month, day) triple.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Formats a time as an ISO 8601 UTC timestamp, e.g. 2021-02-03T04:05:06Z
+pub fn format_iso8601(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(dur) => dur.as_secs() as i64,
+        Err(_) => 0,
+    };
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let rem = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+// A q-value in thousandths, so that they can be compared exactly
+fn parse_qvalue(value: &str) -> Option<u16> {
+    let q = value.trim().parse::<f32>().ok()?;
+    if !(0.0..=1.0).contains(&q) {
+        return None;
+    }
+    Some((q * 1000.0).round() as u16)
+}
+
+// Split a header like Accept or Accept-Encoding ("gzip;q=0.5, br") into
+// lowercased names and their q-values, which default to 1000.
+pub fn parse_qvalue_list(header: &str) -> Vec<(String, u16)> {
+    let mut qvalues = Vec::<(String, u16)>::new();
+    for item in header.split(",") {
+        let mut params = item.split(";");
+        let name = params.next().unwrap_or("").trim().to_lowercase();
+        if name.is_empty() {
+            continue;
+        }
+        let q = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .next()
+            .map(parse_qvalue)
+            .unwrap_or(Some(1000));
+        // An unparseable q-value says nothing useful, so ignore the entry
+        if let Some(q) = q {
+            qvalues.push((name, q));
+        }
+    }
+    qvalues
+}
+
+// Escape `s` for use inside a JSON string
+pub fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn split_query(path: &str) -> (&str, Option<&str>) {
+    match path.find('?') {
+        Some(pos) => (&path[..pos], Some(&path[pos + 1..])),
+        None => (path, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"admin:hunter2"), "YWRtaW46aHVudGVyMg==");
+    }
+
+    #[test]
+    fn iso8601() {
+        use std::time::Duration;
+
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(format_iso8601(at(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(format_iso8601(at(1612325106)), "2021-02-03T04:05:06Z");
+        // A leap day, and the last second of a year
+        assert_eq!(format_iso8601(at(951782400)), "2000-02-29T00:00:00Z");
+        assert_eq!(format_iso8601(at(4102444799)), "2099-12-31T23:59:59Z");
+        // Before the epoch is clamped to it
+        assert_eq!(
+            format_iso8601(UNIX_EPOCH - Duration::from_secs(1)),
+            "1970-01-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn repeated_request_headers() {
+        let req = HttpRequest::new(
+            "GET / HTTP/1.1\r\nHost: example.com:8080\r\nCache-Control: no-cache\r\n\
+             X-Forwarded-For: 10.0.0.1\r\ncache-control: max-age=0\r\nReferer: http://a/b\r\n\r\n",
+        )
+        .unwrap_or_else(|_| panic!("request was rejected"));
+        assert_eq!(
+            req.get_header_all("cache-control"),
+            vec!["no-cache", "max-age=0"]
+        );
+        assert_eq!(
+            req.get_header_all("Cache-Control"),
+            vec!["no-cache", "max-age=0"]
+        );
+        assert_eq!(
+            req.get_header("CACHE-CONTROL").map(|s| &s[..]),
+            Some("no-cache")
+        );
+        assert_eq!(req.get_header_all("x-forwarded-for"), vec!["10.0.0.1"]);
+        assert!(req.get_header_all("accept").is_empty());
+        // Colons in values are kept
+        assert_eq!(
+            req.get_header("host").map(|s| &s[..]),
+            Some("example.com:8080")
+        );
+        assert_eq!(
+            req.get_header("referer").map(|s| &s[..]),
+            Some("http://a/b")
+        );
+
+        // Lengths that disagree leave the end of the body in doubt
+        let conflicting = "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n";
+        assert!(matches!(
+            HttpRequest::new(conflicting),
+            Err(HttpStatus::BadRequest)
+        ));
+        let repeated = "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\n";
+        assert!(HttpRequest::new(repeated).is_ok());
+    }
+
+    #[test]
+    fn bodyless_statuses() {
+        // Lengths and bodies given for them are dropped when the headers are sent
+        for status in &[HttpStatus::NoContent, HttpStatus::NotModified] {
+            let mut resp = HttpResponse::new(*status, &HttpVersion::Http1_1);
+            resp.add_header("ETag".to_string(), "\"1\"".to_string());
+            resp.set_content_length(5);
+            resp.add_body(ResponseDataType::Bytes(io::Cursor::new(b"hello".to_vec())));
+            let mut written = Vec::new();
+            resp.write_headers_to_stream(&mut written).unwrap();
+            assert!(resp.is_finished());
+            assert_eq!(resp.partial_write_to_stream(&mut written).unwrap(), 0);
+            let written = String::from_utf8(written).unwrap();
+            assert!(!written.contains("Content-Length"), "{}", written);
+            assert!(written.contains("\r\nETag: \"1\"\r\n"), "{}", written);
+            // Nothing follows the headers
+            assert!(written.ends_with("\r\n\r\n"), "{}", written);
+            assert_eq!(written.matches("\r\n\r\n").count(), 1, "{}", written);
+        }
+    }
+}
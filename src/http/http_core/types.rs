@@ -1,4 +1,5 @@
 use std::{
+    cmp::min,
     fs,
     io::{self, Read, Seek, SeekFrom},
 };
@@ -21,19 +22,180 @@ impl Read for SeekableString {
     }
 }
 
+// Seeking works as it does for files, except that a position past the end is
+// clamped to the end, so that reads from there return 0. Seeking to before
+// the start is an error.
 impl Seek for SeekableString {
     fn seek(&mut self, pos: SeekFrom) -> Result<u64, io::Error> {
-        self.start = match pos {
-            SeekFrom::Start(i) => i as usize,
-            SeekFrom::Current(i) => ((self.start as i64) + i) as usize,
-            SeekFrom::End(i) => ((self.data.len() as i64) - i) as usize,
+        let len = self.data.len() as i64;
+        let target = match pos {
+            SeekFrom::Start(i) => i.min(len as u64) as i64,
+            SeekFrom::Current(i) => (self.start as i64).saturating_add(i),
+            SeekFrom::End(i) => len.saturating_add(i),
         };
+        if target < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to before the start of the string",
+            ));
+        }
+        self.start = target.min(len) as usize;
         Ok(self.start as u64)
     }
 }
 
+// Several ranges of a file, sent as the parts of a multipart/byteranges body.
+// Each range is read from the file only as it is sent. Seeking works as it
+// does for SeekableString.
+pub struct MultiRange {
+    file: fs::File,
+    pieces: Vec<RangePiece>,
+    pos: usize,
+    length: usize,
+}
+
+enum RangePiece {
+    // A boundary and the headers of the part after it, or the final boundary
+    Framing(Vec<u8>),
+    // The start and length of a range of the file
+    Range(usize, usize),
+}
+
+impl RangePiece {
+    fn len(&self) -> usize {
+        match self {
+            RangePiece::Framing(bytes) => bytes.len(),
+            RangePiece::Range(_, len) => *len,
+        }
+    }
+}
+
+impl MultiRange {
+    // `ranges` are the (start, length) of each part, and must not be empty.
+    // `file_length` is the length of the whole file.
+    pub fn new(
+        file: fs::File,
+        boundary: &str,
+        content_type: &str,
+        ranges: &[(usize, usize)],
+        file_length: usize,
+    ) -> MultiRange {
+        let mut pieces = Vec::with_capacity(ranges.len() * 2 + 1);
+        for &(start, len) in ranges {
+            let head = format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary,
+                content_type,
+                start,
+                start + len - 1,
+                file_length
+            );
+            pieces.push(RangePiece::Framing(head.into_bytes()));
+            pieces.push(RangePiece::Range(start, len));
+        }
+        pieces.push(RangePiece::Framing(
+            format!("\r\n--{}--\r\n", boundary).into_bytes(),
+        ));
+        let length = pieces.iter().map(RangePiece::len).sum();
+        MultiRange {
+            file,
+            pieces,
+            pos: 0,
+            length,
+        }
+    }
+
+    // Length of the whole body: every range, and the framing around them
+    pub fn length(&self) -> usize { self.length }
+}
+
+impl Read for MultiRange {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        // Where the current position falls within its piece
+        let mut offset = self.pos;
+        for piece in &self.pieces {
+            let len = piece.len();
+            if offset >= len {
+                offset -= len;
+                continue;
+            }
+            let want = min(buf.len(), len - offset);
+            let read = match piece {
+                RangePiece::Framing(bytes) => {
+                    buf[..want].copy_from_slice(&bytes[offset..offset + want]);
+                    want
+                }
+                RangePiece::Range(start, _) => {
+                    self.file.seek(SeekFrom::Start((start + offset) as u64))?;
+                    self.file.read(&mut buf[..want])?
+                }
+            };
+            self.pos += read;
+            return Ok(read);
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for MultiRange {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, io::Error> {
+        let len = self.length as i64;
+        let target = match pos {
+            SeekFrom::Start(i) => i.min(len as u64) as i64,
+            SeekFrom::Current(i) => (self.pos as i64).saturating_add(i),
+            SeekFrom::End(i) => len.saturating_add(i),
+        };
+        if target < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to before the start of the body",
+            ));
+        }
+        self.pos = target.min(len) as usize;
+        Ok(self.pos as u64)
+    }
+}
+
 pub enum ResponseDataType {
     String(SeekableString),
     File(fs::File),
+    // A body generated in memory, such as a compressed file
+    Bytes(io::Cursor<Vec<u8>>),
+    Ranges(MultiRange),
     None,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seekable_string_seeking() {
+        let mut s = SeekableString::new("hello world".to_string());
+        let mut rest = String::new();
+
+        // Within the string
+        assert_eq!(s.seek(SeekFrom::Start(6)).unwrap(), 6);
+        s.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "world");
+        assert_eq!(s.seek(SeekFrom::End(-5)).unwrap(), 6);
+        assert_eq!(s.seek(SeekFrom::Current(-6)).unwrap(), 0);
+
+        // To the end
+        assert_eq!(s.seek(SeekFrom::End(0)).unwrap(), 11);
+        assert_eq!(s.read(&mut [0; 4]).unwrap(), 0);
+
+        // Past the end is clamped to it
+        assert_eq!(s.seek(SeekFrom::Start(100)).unwrap(), 11);
+        assert_eq!(s.read(&mut [0; 4]).unwrap(), 0);
+        assert_eq!(s.seek(SeekFrom::Current(100)).unwrap(), 11);
+        assert_eq!(s.seek(SeekFrom::End(5)).unwrap(), 11);
+        assert_eq!(s.read(&mut [0; 4]).unwrap(), 0);
+
+        // Before the start is an error, and leaves the position alone
+        assert_eq!(s.seek(SeekFrom::Start(3)).unwrap(), 3);
+        assert!(s.seek(SeekFrom::Current(-4)).is_err());
+        assert!(s.seek(SeekFrom::End(-12)).is_err());
+        assert_eq!(s.stream_position().unwrap(), 3);
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+// A directory changed this recently may change again without its mtime
+// moving, since timestamps only advance once per clock tick. Such listings
+// are not kept.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+// What a listing depends on, besides the directory's contents
+#[derive(PartialEq, Eq, Hash, Clone)]
+struct ListingKey {
+    path: PathBuf,
+    page: usize,
+    per_page: usize,
+}
+
+struct CachedListing {
+    // The directory's mtime when the listing was rendered
+    mtime: SystemTime,
+    html: String,
+    // From `ListingCache::clock`, for finding the least recently used
+    last_used: u64,
+}
+
+// Rendered HTML listings, reused until their directory's mtime changes. Only
+// adding, removing or renaming entries changes it, so the sizes and times of
+// files modified in place can be out of date.
+pub struct ListingCache {
+    listings: HashMap<ListingKey, CachedListing>,
+    capacity: usize,
+    clock: u64,
+}
+
+impl ListingCache {
+    pub fn new(capacity: usize) -> ListingCache {
+        ListingCache {
+            listings: HashMap::new(),
+            capacity,
+            clock: 0,
+        }
+    }
+
+    // The listing of `path` rendered when the directory had this mtime
+    pub fn get(
+        &mut self,
+        path: &Path,
+        page: usize,
+        per_page: usize,
+        mtime: SystemTime,
+    ) -> Option<String> {
+        let key = ListingKey {
+            path: path.to_path_buf(),
+            page,
+            per_page,
+        };
+        self.clock += 1;
+        let listing = self.listings.get_mut(&key)?;
+        if listing.mtime != mtime {
+            return None;
+        }
+        listing.last_used = self.clock;
+        Some(listing.html.clone())
+    }
+
+    // Keep a listing, evicting the least recently used one if the cache is
+    // full
+    pub fn insert(
+        &mut self,
+        path: &Path,
+        page: usize,
+        per_page: usize,
+        mtime: SystemTime,
+        html: &str,
+    ) {
+        let settled = SystemTime::now()
+            .duration_since(mtime)
+            .is_ok_and(|age| age >= SETTLE_TIME);
+        if self.capacity == 0 || !settled {
+            return;
+        }
+        let key = ListingKey {
+            path: path.to_path_buf(),
+            page,
+            per_page,
+        };
+        if !self.listings.contains_key(&key) && self.listings.len() >= self.capacity {
+            let oldest = self
+                .listings
+                .iter()
+                .min_by_key(|(_, listing)| listing.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.listings.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.listings.insert(
+            key,
+            CachedListing {
+                mtime,
+                html: html.to_string(),
+                last_used: self.clock,
+            },
+        );
+    }
+
+    // For when something other than a directory changes how it is listed
+    pub fn clear(&mut self) { self.listings.clear(); }
+}
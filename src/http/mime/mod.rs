@@ -0,0 +1,148 @@
+use crate::http::http_core::parse_qvalue_list;
+
+use std::path::Path;
+
+// Guess a file's type from its extension. None if the extension is missing
+// or not in the table below; --default-mime decides what those are sent as.
+pub fn mime_from_path(path: &Path) -> Option<&'static str> {
+    let extension = match path.extension() {
+        Some(ext) => ext.to_string_lossy().to_lowercase(),
+        None => {
+            return None;
+        }
+    };
+    Some(match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "txt" | "md5sum" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        _ => {
+            return None;
+        }
+    })
+}
+
+// Which files may be served, going by their extensions, which are kept
+// lowercase and without the '.'. Files without one are only served when
+// there is no allow list.
+#[derive(Clone, Default)]
+pub struct ExtensionFilter {
+    pub allow: Option<Vec<String>>,
+    pub deny: Vec<String>,
+}
+
+impl ExtensionFilter {
+    pub fn allows(&self, path: &Path) -> bool {
+        match path.extension() {
+            Some(ext) => {
+                let ext = ext.to_string_lossy().to_lowercase();
+                !self.deny.contains(&ext)
+                    && self.allow.as_ref().is_none_or(|allow| allow.contains(&ext))
+            }
+            None => self.allow.is_none(),
+        }
+    }
+}
+
+// Pick the type the client likes best out of `available`, with earlier ones
+// winning ties. None if it will take none of them.
+pub fn choose_type(accept: Option<&str>, available: &[&'static str]) -> Option<&'static str> {
+    let mut best = None;
+    let mut best_q = 0;
+    for content_type in available {
+        let q = quality(accept, content_type);
+        if q > best_q {
+            best = Some(*content_type);
+            best_q = q;
+        }
+    }
+    best
+}
+
+// The q-value (out of 1000) that this Accept header gives `content_type`. The
+// most specific matching range decides, so "text/*;q=0, text/html" allows
+// HTML but nothing else under text/.
+fn quality(accept: Option<&str>, content_type: &str) -> u16 {
+    let ranges = match accept {
+        Some(accept) => parse_qvalue_list(accept),
+        None => {
+            return 1000;
+        }
+    };
+    if ranges.is_empty() {
+        return 1000;
+    }
+
+    let essence = content_type
+        .split(";")
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    let wildcard = format!("{}/*", essence.split("/").next().unwrap_or(""));
+    let lookup = |name: &str| ranges.iter().find(|(n, _)| n == name).map(|(_, q)| *q);
+    lookup(&essence)
+        .or_else(|| lookup(&wildcard))
+        .or_else(|| lookup("*/*"))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_media_ranges() {
+        let is_acceptable = |accept, content_type| choose_type(accept, &[content_type]).is_some();
+
+        assert!(is_acceptable(None, "text/html"));
+        assert!(is_acceptable(Some("*/*"), "text/html"));
+        assert!(is_acceptable(
+            Some("text/html,application/xhtml+xml,*/*;q=0.8"),
+            "text/html; charset=utf-8"
+        ));
+        assert!(is_acceptable(Some("text/*"), "text/html"));
+        assert!(is_acceptable(Some("text/*;q=0, text/html"), "text/html"));
+        assert!(!is_acceptable(Some("application/json"), "text/html"));
+        assert!(!is_acceptable(Some("text/html;q=0, */*"), "text/html"));
+    }
+
+    #[test]
+    fn choose_media_type() {
+        let available = ["text/html; charset=utf-8", "text/plain; charset=utf-8"];
+        assert_eq!(choose_type(None, &available), Some(available[0]));
+        assert_eq!(choose_type(Some("*/*"), &available), Some(available[0]));
+        assert_eq!(
+            choose_type(Some("text/plain"), &available),
+            Some(available[1])
+        );
+        assert_eq!(
+            choose_type(Some("text/html;q=0.5, text/plain"), &available),
+            Some(available[1])
+        );
+        assert_eq!(
+            choose_type(Some("text/plain;q=0.5, */*"), &available),
+            Some(available[0])
+        );
+        assert_eq!(choose_type(Some("application/json"), &available), None);
+    }
+}
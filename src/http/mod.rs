@@ -1,36 +1,85 @@
 mod boyer_moore;
+mod cgi;
+mod compression;
+pub mod control;
+pub mod downloads;
 pub mod http_core;
+mod listing_cache;
+pub mod mime;
 mod post_buffer;
+mod proxy;
+mod stats;
+pub mod stream;
+mod watcher;
 
-use boyer_moore_magiclen::BMByte;
+#[cfg(test)]
+mod tests;
 
-use crate::rendering;
-use post_buffer::PostBuffer;
+use boyer_moore_magiclen::BMByte;
 
-use crate::opts::types::Opts;
+use crate::rendering::{
+    self,
+    types::{Column, ListingLayout},
+};
+use cgi::CgiProcess;
+use compression::Encoding;
+use control::ControlCommand;
+use downloads::DownloadCounter;
+use listing_cache::ListingCache;
+use mime::ExtensionFilter;
+use post_buffer::{PostBuffer, UploadPart};
+use proxy::{ProxyConnection, ProxyRoute};
+use stats::ServerStats;
+use stream::HttpStream;
+use watcher::Watcher;
+
+use crate::opts::{self, types::Opts};
 
 use http_core::{
-    types::{ResponseDataType, SeekableString},
-    HttpMethod, HttpRequest, HttpResponse, HttpStatus, HttpVersion,
+    base64_encode, check_method_token, format_iso8601, method_to_str, status_closes_connection,
+    status_forbids_body,
+    types::{MultiRange, ResponseDataType, SeekableString},
+    version_to_str, HttpMethod, HttpRequest, HttpResponse, HttpStatus, HttpVersion,
 };
 
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+};
 
 use nix::{
-    sys::select::{select, FdSet},
+    errno::Errno,
+    sys::{
+        select::{select, FdSet},
+        socket::{
+            bind, listen, setsockopt, socket, sockopt::ReuseAddr, AddressFamily, InetAddr,
+            SockAddr, SockFlag, SockType,
+        },
+        time::{TimeVal, TimeValLike},
+    },
     unistd,
 };
-use std::os::unix::{io::AsRawFd, prelude::RawFd};
+use std::os::unix::{
+    io::{AsRawFd, FromRawFd},
+    prelude::RawFd,
+};
 
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use std::{
+    cell::RefCell,
     fs,
     io::{self, Read, Seek},
     net::{SocketAddr, TcpListener, TcpStream},
 };
 
-use std::sync::mpsc;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use std::cmp::{max, min};
 
@@ -38,14 +87,93 @@ use std::{format, str::from_utf8};
 
 const BUFFER_SIZE: usize = 4096;
 
+const ROBOTS_TXT_PATH: &str = "/robots.txt";
+const SECURITY_TXT_PATH: &str = "/.well-known/security.txt";
+const EVENTS_PATH: &str = "/__events";
+const DOWNLOADS_PATH: &str = "/_downloads";
+const ADMIN_TOGGLE_PATH: &str = "/__admin/toggle";
+const DEBUG_ECHO_PATH: &str = "/__echo";
+// How long to stop accepting after running out of file descriptors
+const ACCEPT_BACKOFF_MS: u64 = 100;
+
+fn nix_to_io_error(error: nix::Error) -> io::Error {
+    match error.as_errno() {
+        Some(errno) => io::Error::from_raw_os_error(errno as i32),
+        None => io::Error::other(error),
+    }
+}
+
+// Like `TcpListener::bind`, trying each address in turn, but listening with
+// the given backlog rather than the standard library's 128
+fn bind_listener(addrs: &[SocketAddr], backlog: usize) -> Result<TcpListener, io::Error> {
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no addresses to bind to");
+    for addr in addrs {
+        let family = match addr {
+            SocketAddr::V4(_) => AddressFamily::Inet,
+            SocketAddr::V6(_) => AddressFamily::Inet6,
+        };
+        let fd = socket(family, SockType::Stream, SockFlag::SOCK_CLOEXEC, None)
+            .map_err(nix_to_io_error)?;
+        // Owned from here on, so that it is closed on errors
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        let bound = setsockopt(fd, ReuseAddr, &true)
+            .and_then(|_| bind(fd, &SockAddr::new_inet(InetAddr::from_std(addr))))
+            .and_then(|_| listen(fd, backlog));
+        match bound {
+            Ok(()) => return Ok(listener),
+            Err(e) => last_error = nix_to_io_error(e),
+        }
+    }
+    Err(last_error)
+}
+
 fn resolve_io_error(error: &io::Error) -> Option<HttpStatus> {
     match error.kind() {
         io::ErrorKind::NotFound => Some(HttpStatus::NotFound),
         io::ErrorKind::PermissionDenied => Some(HttpStatus::PermissionDenied),
+        // A symbolic link that (eventually) points at itself
+        _ if error.raw_os_error() == Some(Errno::ELOOP as i32) => Some(HttpStatus::NotFound),
         _ => None,
     }
 }
 
+// The answer for a file that is missing, or that may not be served, so that
+// the two cannot be told apart.
+fn not_found() -> HttpResult {
+    HttpResult::Error(
+        HttpStatus::NotFound,
+        Some("No such file or directory.".to_string()),
+    )
+}
+
+// How a request body is framed, going by its Transfer-Encoding
+#[derive(PartialEq)]
+enum TransferCoding {
+    // Given by Content-Length, if there is a body at all
+    None,
+    Chunked,
+    // Gzipped, then chunked. Undone in the opposite order.
+    GzipChunked,
+}
+
+// "identity" changes nothing, so it is left out. Codings that cannot be
+// undone give a 501.
+fn get_transfer_coding(req: &HttpRequest) -> Result<TransferCoding, HttpStatus> {
+    let codings: Vec<String> = req
+        .get_header_all("transfer-encoding")
+        .iter()
+        .flat_map(|header| header.split(','))
+        .map(|coding| coding.trim().to_lowercase())
+        .filter(|coding| !coding.is_empty() && coding != "identity")
+        .collect();
+    match codings.iter().map(|coding| &coding[..]).collect::<Vec<_>>()[..] {
+        [] => Ok(TransferCoding::None),
+        ["chunked"] => Ok(TransferCoding::Chunked),
+        ["gzip" | "x-gzip", "chunked"] => Ok(TransferCoding::GzipChunked),
+        _ => Err(HttpStatus::NotImplemented),
+    }
+}
+
 struct ContentRange {
     pub start: usize,
     pub len: Option<usize>,
@@ -110,6 +238,15 @@ fn decode_content_range(range_str: &str) -> Option<ContentRange> {
     }
 }
 
+// Split a header like "bytes=0-99,200-299" into its ranges
+fn decode_content_ranges(range_str: &str) -> Option<Vec<ContentRange>> {
+    let specs = range_str.strip_prefix("bytes=")?;
+    specs
+        .split(',')
+        .map(|spec| decode_content_range(&format!("bytes={}", spec.trim())))
+        .collect()
+}
+
 fn decode_request(req_body: &[u8]) -> Result<HttpRequest, HttpStatus> {
     let request_str = match from_utf8(req_body) {
         Ok(dec) => dec,
@@ -127,17 +264,34 @@ pub enum ConnectionState {
     ReadingRequest,
     ReadingPostBody,
     WritingResponse,
+    // Held open to send server-sent events as files change
+    StreamingEvents,
+    // Passing a request body on to a CGI script
+    ReadingCgiBody,
+    // Waiting for a CGI script to finish its output
+    RunningCgi,
+    // Passing a request and its body on to an upstream server
+    ProxyingRequest,
+    // Passing the upstream's response back to the client
+    ProxyingResponse,
     Closing,
 }
 
 pub struct HttpConnection {
-    pub stream: TcpStream,
+    pub stream: Box<dyn HttpStream>,
     pub state: ConnectionState,
 
-    // Buffer for holding a pending request
-    pub buffer: Box<[u8; BUFFER_SIZE]>,
+    // Accumulates the head of a pending request, with its own limit of
+    // --max-header-size. It starts at BUFFER_SIZE, and grows to that limit
+    // if it is larger. Whatever of the body arrived with the head follows
+    // it, and is handed on through `initial_body`.
+    pub buffer: Vec<u8>,
     pub bytes_read: usize,
+    // Where the head ends and the body begins
     pub body_start_location: usize,
+    // Everything before this has already been searched for the end of the
+    // headers, so each read only needs to search what it added.
+    pub scan_from: usize,
 
     pub post_buffer: Option<PostBuffer>,
 
@@ -147,118 +301,632 @@ pub struct HttpConnection {
     pub last_requested_method: Option<HttpMethod>,
     pub last_requested_uri: Option<String>,
     pub num_requests: usize,
+    // Whether the current request, or the connection if it has made none,
+    // has been written to the history
+    pub logged: bool,
 
     pub keep_alive: bool,
 
+    // Whether errors for the current request are sent as JSON rather than
+    // HTML, going by its Accept header
+    pub json_errors: bool,
+
+    // Whether the client asked not to be tracked, going by DNT
+    pub do_not_track: bool,
+
     pub bytes_requested: usize,
     pub bytes_sent: usize,
+
+    // The file being sent, counted as downloaded once the response is done
+    pub download: Option<PathBuf>,
+
+    pub cgi: Option<CgiProcess>,
+
+    pub proxy: Option<ProxyConnection>,
+
+    // When the client's socket was last ready for what the connection was
+    // waiting to do
+    pub last_progress: Instant,
 }
 
 impl HttpConnection {
-    pub fn new(stream: TcpStream) -> HttpConnection {
+    pub fn new(stream: impl HttpStream + 'static) -> HttpConnection {
         return HttpConnection {
-            stream: stream,
+            stream: Box::new(stream),
             state: ConnectionState::ReadingRequest,
-            buffer: Box::new([0; BUFFER_SIZE]),
+            buffer: vec![0; BUFFER_SIZE],
             bytes_read: 0,
             body_start_location: 0,
+            scan_from: 0,
             post_buffer: None,
             response: None,
             keep_alive: true,
+            json_errors: false,
+            do_not_track: false,
             bytes_requested: 0,
             bytes_sent: 0,
             last_requested_uri: None,
             last_requested_method: None,
             num_requests: 0,
+            logged: false,
+            download: None,
+            cgi: None,
+            proxy: None,
+            last_progress: Instant::now(),
         };
     }
 
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> { self.stream.peer_addr() }
+
+    // The part of the body read along with the head. Bodies are read into
+    // their own buffers from then on.
+    pub fn initial_body(&self) -> &[u8] { &self.buffer[self.body_start_location..self.bytes_read] }
+
     pub fn reset(&mut self) {
         self.bytes_read = 0;
+        self.scan_from = 0;
         self.response = None;
         self.post_buffer = None;
+        // Errors before the next head is decoded have no method to go by
+        self.last_requested_method = None;
+        self.last_requested_uri = None;
+        self.json_errors = false;
+        self.do_not_track = false;
+        self.download = None;
+        self.cgi = None;
+        self.proxy = None;
     }
 }
 
+// Which symbolic links may be followed when resolving a request
+#[derive(PartialEq, Clone, Copy)]
+enum SymlinkMode {
+    // None; a path through a link is not found
+    Off,
+    // Those whose targets are under the root
+    Internal,
+    // All of them, even to targets outside the root
+    All,
+}
+
 enum HttpResult {
     Response(HttpResponse, usize),
+    // A file, with its canonical path so that downloading it can be counted
+    File(HttpResponse, usize, PathBuf),
     Error(HttpStatus, Option<String>),
     ReadRequestBody,
+    EventStream(HttpResponse),
 }
 
 pub struct HttpTui<'a> {
     listener: TcpListener,
     root_dir: &'a Path,
+    // From --root, canonical, and searched after `root_dir` in order
+    fallback_roots: Vec<PathBuf>,
     history_channel: mpsc::Sender<String>,
     dir_listings: bool,
-    disabled: bool,
+    no_root_listing: bool,
+    // Shared with the display, which shows whether requests are served
+    disabled: Arc<AtomicBool>,
+    disabled_status: HttpStatus,
     uploading: bool,
     upload_size_limit: usize,
-    index_file: &'a str,
+    max_header_size: usize,
+    max_path_depth: usize,
+    max_component_length: usize,
+    fast_reject_uploads: bool,
+    reject_get_bodies: bool,
+    discard_limit: usize,
+    continue_threshold: usize,
+    forced_upload_extension: Option<String>,
+    plus_as_space: bool,
+    decompress_uploads: bool,
+    // Whether forms posted to DEBUG_ECHO_PATH are summarized
+    debug_echo: bool,
+    // The Authorization header that admin requests must carry
+    admin_authorization: Option<String>,
+    index_file: String,
     no_index_file: bool,
     no_append_slash: bool,
+    no_symlink_slash: bool,
+    follow_symlinks: SymlinkMode,
+    // Absolute, and under the root
+    cgi_dir: Option<PathBuf>,
+    // Methods scripts may be run with
+    cgi_methods: Vec<HttpMethod>,
+    proxies: Vec<ProxyRoute>,
+    // Methods passed on to upstreams. Uploads being off does not matter to
+    // them, since nothing is stored here.
+    proxy_methods: Vec<HttpMethod>,
+    // How long upstreams have to start their responses
+    proxy_timeout: Option<Duration>,
+    // How long clients may go without sending or receiving anything while
+    // they are waited on. Also bounds single reads and writes, since the
+    // sockets are blocking and a write of a whole buffer can outlast
+    // select() saying that there was room for some of it.
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    minimal_logging: bool,
+    honor_dnt: bool,
+    // Keys the hash that anonymous clients are shown as. Random, so that
+    // tokens cannot be matched to addresses or across runs.
+    log_salt: RandomState,
+    // Prefix of every generated link, without a trailing '/', e.g. "/share"
+    base_path: String,
+    no_ranges: bool,
+    max_ranges: usize,
+    // None for the size of the file being sent
+    max_range_bytes: Option<usize>,
+    allowed_methods: Vec<HttpMethod>,
+    listing_layout: ListingLayout,
+    nosniff: bool,
+    no_auto_nosniff: bool,
+    default_mime: String,
+    compress: bool,
+    compress_min_size: usize,
+    // From --header
+    extra_headers: Vec<(String, String)>,
+    // Default bodies for reserved paths, used when the file does not exist
+    robots_txt: Option<String>,
+    security_txt: Option<String>,
+    watcher: Option<Watcher>,
+    downloads: Option<RefCell<DownloadCounter>>,
+    // Only kept with --listing-cache
+    listing_cache: Option<RefCell<ListingCache>>,
+    // Only kept with --summary-on-exit
+    stats: Option<RefCell<ServerStats>>,
+    // Decides whether a client may make a request at all
+    authorizer: Option<Box<Authorizer<'a>>>,
+    // Decides whether to keep a connection as soon as it is accepted
+    accept_filter: Option<Box<AcceptFilter<'a>>>,
+    // Where ControlCommand::Reload gets its options from
+    reloader: Option<Box<Reloader<'a>>>,
+    // What the listener was bound with, which a reload cannot change
+    bind_address: (String, u16),
 }
 
-impl HttpTui<'_> {
-    pub fn new<'a>(
+// Returning false answers the request with a 403.
+pub type Authorizer<'a> = dyn Fn(&HttpRequest, &SocketAddr) -> bool + 'a;
+
+// Returning false closes the connection before anything is read from it.
+pub type AcceptFilter<'a> = dyn Fn(&SocketAddr) -> bool + 'a;
+
+// Gives the options to apply on a reload, or why there are none.
+pub type Reloader<'a> = dyn Fn() -> Result<Opts, String> + 'a;
+
+impl<'a> HttpTui<'a> {
+    pub fn new(
+        root_dir: &'a Path,
+        sender: mpsc::Sender<String>,
+        opts: &'a Opts,
+    ) -> Result<HttpTui<'a>, io::Error> {
+        let addrs = opts::parse_bind_address(&opts.hostmask, opts.port)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let listener = bind_listener(&addrs, opts.backlog).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("could not bind to port {}: {}", opts.port, e),
+            )
+        })?;
+        HttpTui::from_listener(root_dir, listener, sender, opts)
+    }
+
+    // Serve on a listener that is already bound, like one inherited through
+    // systemd socket activation. The port and hostmask options are ignored.
+    pub fn from_listener(
         root_dir: &'a Path,
+        listener: TcpListener,
         sender: mpsc::Sender<String>,
         opts: &'a Opts,
     ) -> Result<HttpTui<'a>, io::Error> {
-        let listener = TcpListener::bind(format!(
-            "{mask}:{port}",
-            mask = &opts.hostmask,
-            port = &opts.port
-        ))?;
+        // Embedders may not have checked them, and much below relies on it
+        opts::check_opts(opts).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let (allowed_methods, cgi_methods, proxy_methods) = methods_from_opts(opts);
+        let robots_txt = match opts.robots.as_str() {
+            "disallow" => Some("User-agent: *\nDisallow: /\n".to_string()),
+            "allow" => Some("User-agent: *\nDisallow:\n".to_string()),
+            _ => None,
+        };
+        let security_txt = opts.security_contact.as_ref().map(|contact| {
+            let expires = SystemTime::now() + Duration::from_secs(365 * 24 * 60 * 60);
+            format!(
+                "Contact: {}\nExpires: {}\n",
+                contact,
+                format_iso8601(expires)
+            )
+        });
+        let watcher = if opts.watch {
+            match Watcher::new(root_dir) {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    eprintln!(
+                        "Warning: could not watch {} for changes: {}",
+                        root_dir.display(),
+                        e
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let cgi_dir = match &opts.cgi_dir {
+            Some(dir) => {
+                let path = root_dir.join(dir.trim_start_matches('/'));
+                match fs::canonicalize(&path) {
+                    Ok(path) if path.starts_with(root_dir) && path.is_dir() => Some(path),
+                    _ => {
+                        // Scripts must stay inside what is being served
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "--cgi-dir {} is not a directory inside {}",
+                                dir,
+                                root_dir.display()
+                            ),
+                        ));
+                    }
+                }
+            }
+            None => None,
+        };
+        let fallback_roots: Vec<PathBuf> = opts
+            .roots
+            .iter()
+            .map(|dir| match fs::canonicalize(dir) {
+                Ok(path) if path.is_dir() => Ok(path),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("--root {} is not a directory", dir),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+        let downloads = if opts.count_downloads || opts.downloads_file.is_some() {
+            let file = opts.downloads_file.as_ref().map(Path::new);
+            let roots = std::iter::once(root_dir.to_path_buf())
+                .chain(fallback_roots.iter().cloned())
+                .collect();
+            match DownloadCounter::new(file, roots) {
+                Ok(downloads) => Some(RefCell::new(downloads)),
+                Err(e) => {
+                    // Starting from zero would overwrite the saved counts
+                    // with the first download.
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!(
+                            "could not read download counts from {}: {}",
+                            opts.downloads_file.as_deref().unwrap_or_default(),
+                            e
+                        ),
+                    ));
+                }
+            }
+        } else {
+            None
+        };
+        let mut proxies = Vec::new();
+        for proxy in &opts.proxies {
+            let (prefix, authority, path) = opts::parse_proxy(proxy).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid --proxy: {}", e),
+                )
+            })?;
+            let route = ProxyRoute::new(&prefix, &authority, path.as_deref()).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("could not look up {} for --proxy: {}", authority, e),
+                )
+            })?;
+            proxies.push(route);
+        }
         Ok(HttpTui {
             listener: listener,
             root_dir: root_dir,
+            fallback_roots,
             history_channel: sender,
             dir_listings: !opts.disable_directory_listings,
-            disabled: opts.start_disabled,
+            no_root_listing: opts.no_root_listing,
+            disabled: Arc::new(AtomicBool::new(opts.start_disabled)),
+            // Checked above
+            disabled_status: http_core::status_from_code(opts.disabled_status)
+                .unwrap_or(HttpStatus::ServiceUnavailable),
             uploading: opts.uploading_enabled,
             upload_size_limit: opts.size_limit,
-            index_file: &opts.index_file,
+            max_header_size: opts.max_header_size,
+            max_path_depth: opts.max_path_depth,
+            max_component_length: opts.max_component_length,
+            fast_reject_uploads: opts.fast_reject_uploads,
+            reject_get_bodies: opts.reject_get_bodies,
+            discard_limit: opts.discard_limit,
+            continue_threshold: opts.continue_threshold,
+            admin_authorization: opts
+                .admin_credentials
+                .as_ref()
+                .map(|credentials| format!("Basic {}", base64_encode(credentials.as_bytes()))),
+            forced_upload_extension: opts
+                .force_upload_ext
+                .as_ref()
+                .map(|ext| ext.trim_start_matches('.').to_string()),
+            plus_as_space: opts.plus_as_space,
+            decompress_uploads: opts.decompress_uploads,
+            debug_echo: opts.debug_echo,
+            index_file: opts.index_file.clone(),
             no_index_file: opts.no_index_file,
             no_append_slash: opts.no_append_slash,
+            no_symlink_slash: opts.no_symlink_slash,
+            base_path: opts::normalize_base_path(&opts.base_path),
+            // Validated by clap
+            follow_symlinks: match opts.follow_symlinks.as_str() {
+                "off" => SymlinkMode::Off,
+                "all" => SymlinkMode::All,
+                _ => SymlinkMode::Internal,
+            },
+            no_ranges: opts.no_ranges,
+            max_ranges: opts.max_ranges,
+            max_range_bytes: match opts.max_range_bytes {
+                0 => None,
+                bytes => Some(bytes),
+            },
+            allowed_methods: allowed_methods,
+            cgi_dir,
+            cgi_methods,
+            proxies,
+            proxy_methods,
+            minimal_logging: opts.minimal_logging,
+            honor_dnt: opts.honor_dnt,
+            log_salt: RandomState::new(),
+            proxy_timeout: match opts.proxy_timeout {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            read_timeout: match opts.read_timeout {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            write_timeout: match opts.write_timeout {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            // Columns are checked above, so this cannot fail.
+            listing_layout: ListingLayout {
+                columns: opts::parse_columns(&opts.columns).unwrap_or_default(),
+                header: opts.listing_header.clone(),
+                footer: opts.listing_footer.clone(),
+                extensions: ExtensionFilter {
+                    allow: opts.allow_ext.as_deref().map(opts::parse_extensions),
+                    deny: opts
+                        .deny_ext
+                        .as_deref()
+                        .map(opts::parse_extensions)
+                        .unwrap_or_default(),
+                },
+            },
+            nosniff: opts.nosniff,
+            no_auto_nosniff: opts.no_auto_nosniff,
+            default_mime: opts.default_mime.clone(),
+            compress: opts.compress,
+            compress_min_size: opts.compress_min_size,
+            // Headers are checked above, so none are dropped here.
+            extra_headers: opts
+                .headers
+                .iter()
+                .filter_map(|header| opts::parse_header(header).ok())
+                .collect(),
+            robots_txt: robots_txt,
+            security_txt: security_txt,
+            watcher: watcher,
+            downloads: downloads,
+            listing_cache: match opts.listing_cache {
+                0 => None,
+                capacity => Some(RefCell::new(ListingCache::new(capacity))),
+            },
+            stats: if opts.summary_on_exit {
+                Some(RefCell::new(ServerStats::new()))
+            } else {
+                None
+            },
+            authorizer: None,
+            accept_filter: None,
+            reloader: None,
+            bind_address: (opts.hostmask.clone(), opts.port),
         })
     }
 
+    pub fn get_disabled_flag(&self) -> Arc<AtomicBool> { self.disabled.clone() }
+
+    fn toggle_disabled(&self) -> bool {
+        let disabled = !self.disabled.load(Ordering::Acquire);
+        self.disabled.store(disabled, Ordering::Release);
+        disabled
+    }
+
+    // Only called when hypershare is embedded; the binary never sets one.
+    #[allow(dead_code)]
+    pub fn set_authorizer(&mut self, authorizer: impl Fn(&HttpRequest, &SocketAddr) -> bool + 'a) {
+        self.authorizer = Some(Box::new(authorizer));
+    }
+
+    // Only called when hypershare is embedded; the binary never sets one.
+    #[allow(dead_code)]
+    pub fn set_accept_filter(&mut self, filter: impl Fn(&SocketAddr) -> bool + 'a) {
+        self.accept_filter = Some(Box::new(filter));
+    }
+
+    pub fn set_reloader(&mut self, reloader: impl Fn() -> Result<Opts, String> + 'a) {
+        self.reloader = Some(Box::new(reloader));
+    }
+
+    // Apply the options that can change while running: the index file,
+    // directory listings, uploading, the upload size limit, and the methods
+    // served, run by scripts, and passed on to proxies. Others are left as
+    // they are, with a warning if they differ.
+    pub fn reload(&mut self, opts: &Opts) {
+        if let Err(e) = opts::check_opts(opts) {
+            let _ = self.history_channel.send(format!("Not reloading, {}", e));
+            return;
+        }
+        let same_dir = fs::canonicalize(&opts.directory).is_ok_and(|dir| dir == self.root_dir);
+        let fixed = [
+            ("--directory", same_dir),
+            ("--hostmask", opts.hostmask == self.bind_address.0),
+            ("--port", opts.port == self.bind_address.1),
+        ];
+        for (name, unchanged) in &fixed {
+            if !unchanged {
+                let _ = self.history_channel.send(format!(
+                    "Warning: {} cannot change while running, ignoring it",
+                    name
+                ));
+            }
+        }
+
+        // Listings show the upload form, which may have come or gone
+        if let Some(cache) = &self.listing_cache {
+            cache.borrow_mut().clear();
+        }
+
+        self.index_file = opts.index_file.clone();
+        self.no_index_file = opts.no_index_file;
+        self.dir_listings = !opts.disable_directory_listings;
+        self.uploading = opts.uploading_enabled;
+        self.upload_size_limit = opts.size_limit;
+        let (allowed_methods, cgi_methods, proxy_methods) = methods_from_opts(opts);
+        self.allowed_methods = allowed_methods;
+        self.cgi_methods = cgi_methods;
+        self.proxy_methods = proxy_methods;
+        let _ = self
+            .history_channel
+            .send("Reloaded configuration".to_string());
+    }
+
+    fn reload_from_reloader(&mut self) {
+        let opts = match &self.reloader {
+            Some(reloader) => reloader(),
+            None => {
+                let _ = self
+                    .history_channel
+                    .send("Not reloading, there is nothing to reload from".to_string());
+                return;
+            }
+        };
+        match opts {
+            Ok(opts) => self.reload(&opts),
+            Err(e) => {
+                let _ = self.history_channel.send(format!("Not reloading, {}", e));
+            }
+        }
+    }
+
     pub fn run(&mut self, pipe_read: RawFd, func: impl Fn(&HashMap<RawFd, HttpConnection>)) {
         let mut connections = HashMap::<RawFd, HttpConnection>::new();
         let l_raw_fd = self.listener.as_raw_fd();
+        let watcher_fd = self.watcher.as_ref().map(|w| w.get_fd());
+        // Set while we are out of file descriptors. The listener stays
+        // readable, so selecting on it would just spin on failed accepts.
+        let mut accept_paused_until: Option<Instant> = None;
+        // Only report running out once, rather than on every retry
+        let mut out_of_fds = false;
 
         'main: loop {
             let mut r_fds = FdSet::new();
             let mut w_fds = FdSet::new();
             let mut e_fds = FdSet::new();
 
+            if let Some(until) = accept_paused_until {
+                if Instant::now() >= until {
+                    accept_paused_until = None;
+                }
+            }
+
             // First add listener:
-            r_fds.insert(l_raw_fd);
-            e_fds.insert(l_raw_fd);
+            if accept_paused_until.is_none() {
+                r_fds.insert(l_raw_fd);
+                e_fds.insert(l_raw_fd);
+            }
 
             r_fds.insert(pipe_read);
             e_fds.insert(pipe_read);
 
+            if let Some(fd) = watcher_fd {
+                r_fds.insert(fd);
+            }
+
+            // The connection each CGI script's output or upstream server
+            // belongs to
+            let mut backend_fds = HashMap::<RawFd, RawFd>::new();
+
             for (fd, http_conn) in &connections {
                 match http_conn.state {
                     ConnectionState::WritingResponse => {
                         w_fds.insert(*fd);
                     }
-                    ConnectionState::ReadingRequest | ConnectionState::ReadingPostBody => {
+                    ConnectionState::ReadingRequest
+                    | ConnectionState::ReadingPostBody
+                    | ConnectionState::StreamingEvents
+                    | ConnectionState::ReadingCgiBody => {
                         r_fds.insert(*fd);
                     }
+                    ConnectionState::RunningCgi => {
+                        if let Some(cgi) = &http_conn.cgi {
+                            r_fds.insert(cgi.get_fd());
+                            backend_fds.insert(cgi.get_fd(), *fd);
+                        }
+                    }
+                    // Data only moves one way at a time: what has been read
+                    // is written on before anything more is read.
+                    ConnectionState::ProxyingRequest => {
+                        if let Some(proxy) = &http_conn.proxy {
+                            if proxy.has_request_data() {
+                                w_fds.insert(proxy.get_fd());
+                                backend_fds.insert(proxy.get_fd(), *fd);
+                            } else {
+                                r_fds.insert(*fd);
+                            }
+                        }
+                    }
+                    ConnectionState::ProxyingResponse => {
+                        if let Some(proxy) = &http_conn.proxy {
+                            if proxy.has_response_data() {
+                                w_fds.insert(*fd);
+                            } else {
+                                r_fds.insert(proxy.get_fd());
+                                backend_fds.insert(proxy.get_fd(), *fd);
+                            }
+                        }
+                    }
                     _ => {}
                 }
                 e_fds.insert(*fd);
             }
 
+            // Wake up in time to start accepting again, or to give up on an
+            // upstream or a stalled client
+            let proxy_deadline = connections
+                .values()
+                .filter_map(|conn| conn.proxy.as_ref()?.get_deadline())
+                .min();
+            let stall_deadline = connections
+                .values()
+                .filter_map(|conn| self.stall_deadline(conn))
+                .min();
+            let wake_at = [accept_paused_until, proxy_deadline, stall_deadline]
+                .iter()
+                .flatten()
+                .min()
+                .copied();
+            let mut timeout = wake_at.map(|until| {
+                let remaining = until.saturating_duration_since(Instant::now());
+                // Rounded up, so as not to wake up just before it
+                TimeVal::milliseconds(remaining.as_millis() as i64 + 1)
+            });
+
             match select(
                 None,
                 Some(&mut r_fds),
                 Some(&mut w_fds),
                 Some(&mut e_fds),
-                None,
+                timeout.as_mut(),
             ) {
                 Ok(_res) => {}
                 Err(e) => {
@@ -285,35 +953,79 @@ impl HttpTui<'_> {
                                 if size == 0 {
                                     break 'main;
                                 }
-                                if buf[0] as char == 't' {
-                                    self.disabled = !self.disabled;
-                                }
-                                if buf[0] as char == 'k' {
-                                    force_close = true;
-                                }
-                                if buf[0] as char == 'p' {
-                                    // Poked :)
-                                    // This is used to trigger another call
-                                    // to `func`.
+                                match ControlCommand::from_byte(buf[0]) {
+                                    Some(ControlCommand::Toggle) => {
+                                        self.toggle_disabled();
+                                    }
+                                    Some(ControlCommand::CloseAll) => {
+                                        force_close = true;
+                                    }
+                                    // Waking up was the point
+                                    Some(ControlCommand::Poke) => {}
+                                    Some(ControlCommand::Reload) => {
+                                        self.reload_from_reloader();
+                                    }
+                                    None => {}
                                 }
                                 continue;
                             } else {
                                 break 'main;
                             }
                         }
+                        if Some(fd) == watcher_fd {
+                            self.broadcast_changes(&mut connections);
+                            continue;
+                        }
                         if fd == l_raw_fd {
                             // If listener, get accept new connection and add it.
-                            if let Ok((stream, _addr)) = self.listener.accept() {
-                                let conn = HttpTui::create_http_connection(stream);
-                                let pfd = conn.stream.as_raw_fd();
-                                connections.insert(pfd, conn);
+                            match self.listener.accept() {
+                                Ok((stream, addr)) => {
+                                    out_of_fds = false;
+                                    if let Some(filter) = &self.accept_filter {
+                                        if !filter(&addr) {
+                                            // Dropping the stream closes it
+                                            continue;
+                                        }
+                                    }
+                                    if stream.set_read_timeout(self.read_timeout).is_err()
+                                        || stream.set_write_timeout(self.write_timeout).is_err()
+                                    {
+                                        continue;
+                                    }
+                                    let pfd = stream.as_raw_fd();
+                                    let conn = HttpTui::create_http_connection(stream);
+                                    connections.insert(pfd, conn);
+                                }
+                                Err(error) => {
+                                    let errno = error.raw_os_error();
+                                    if errno == Some(Errno::EMFILE as i32)
+                                        || errno == Some(Errno::ENFILE as i32)
+                                    {
+                                        if !out_of_fds {
+                                            let _ = self.history_channel.send(format!(
+                                                "Out of file descriptors, pausing new \
+                                                 connections: {}",
+                                                error
+                                            ));
+                                        }
+                                        out_of_fds = true;
+                                        accept_paused_until = Some(
+                                            Instant::now()
+                                                + Duration::from_millis(ACCEPT_BACKOFF_MS),
+                                        );
+                                    }
+                                }
                             }
                             // We cannot pass this new connection to handle_conn immediately,
                             // as we don't know if there is any data for us to read yet.
                             continue;
                         }
+                        let conn_fd = *backend_fds.get(&fd).unwrap_or(&fd);
                         // TODO: Error checking here
-                        let mut conn = connections.get_mut(&fd).unwrap();
+                        let mut conn = connections.get_mut(&conn_fd).unwrap();
+                        if conn_fd == fd {
+                            conn.last_progress = Instant::now();
+                        }
                         match self.handle_conn_sigpipe(&mut conn) {
                             Ok(_) => {}
                             Err(error) => {
@@ -335,8 +1047,18 @@ impl HttpTui<'_> {
                             continue;
                         }
                         // if !connections.contains_key(&fd) { continue; }
-                        assert_eq!(connections[&fd].state, ConnectionState::WritingResponse);
-                        match self.handle_conn_sigpipe(&mut connections.get_mut(&fd).unwrap()) {
+                        let conn_fd = *backend_fds.get(&fd).unwrap_or(&fd);
+                        assert!(matches!(
+                            connections[&conn_fd].state,
+                            ConnectionState::WritingResponse
+                                | ConnectionState::ProxyingRequest
+                                | ConnectionState::ProxyingResponse
+                        ));
+                        let conn = connections.get_mut(&conn_fd).unwrap();
+                        if conn_fd == fd {
+                            conn.last_progress = Instant::now();
+                        }
+                        match self.handle_conn_sigpipe(conn) {
                             Ok(_) => {}
                             _ => {} /* Err(error) => { write_error(format!("Server error while
                                      * writing: {}", error)); } */
@@ -367,53 +1089,159 @@ impl HttpTui<'_> {
                 }
             }
 
+            let now = Instant::now();
+            for conn in connections.values_mut() {
+                let expired = match &conn.proxy {
+                    Some(proxy) => proxy.get_deadline().is_some_and(|deadline| deadline <= now),
+                    None => false,
+                };
+                if expired {
+                    conn.state = self.proxy_timed_out(conn);
+                }
+                if self
+                    .stall_deadline(conn)
+                    .is_some_and(|deadline| deadline <= now)
+                {
+                    conn.state = ConnectionState::Closing;
+                }
+            }
+
             let to_remove: Vec<_> = connections
                 .iter()
                 .filter(|&(_, conn)| conn.state == ConnectionState::Closing || force_close)
                 .map(|(k, _)| k.clone())
                 .collect();
+            if !to_remove.is_empty() {
+                // Closing connections frees descriptors, so try again now
+                accept_paused_until = None;
+            }
             for fd in to_remove {
-                if let Some(conn) = connections.get(&fd) {
-                    if conn.num_requests == 0 {
-                        self.write_conn_to_history(conn);
-                    }
+                if let Some(mut conn) = connections.remove(&fd) {
+                    self.log_request(&mut conn);
                 }
-                connections.remove(&fd);
             }
             func(&connections);
         }
+
+        if let Some(stats) = &self.stats {
+            for line in stats.borrow().summary() {
+                let _ = self.history_channel.send(line);
+            }
+        }
+    }
+
+    // When a connection waiting on its client is closed, unless the client
+    // sends or takes something first
+    fn stall_deadline(&self, conn: &HttpConnection) -> Option<Instant> {
+        let timeout = match conn.state {
+            ConnectionState::WritingResponse => self.write_timeout,
+            ConnectionState::ReadingRequest
+            | ConnectionState::ReadingPostBody
+            | ConnectionState::ReadingCgiBody => self.read_timeout,
+            _ => None,
+        }?;
+        Some(conn.last_progress + timeout)
+    }
+
+    fn broadcast_changes(&mut self, connections: &mut HashMap<RawFd, HttpConnection>) {
+        let changes = match self.watcher.as_mut() {
+            Some(watcher) => watcher.read_changes(),
+            None => {
+                return;
+            }
+        };
+
+        for conn in connections.values_mut() {
+            if conn.state != ConnectionState::StreamingEvents {
+                continue;
+            }
+            for path in &changes {
+                // A newline would end the event early, so strip any from the path.
+                let event = format!("data: {}\n\n", path.replace(&['\r', '\n'][..], ""));
+                if let Some(resp) = &mut conn.response {
+                    if resp
+                        .write_chunk(&mut conn.stream, event.as_bytes())
+                        .is_err()
+                    {
+                        conn.state = ConnectionState::Closing;
+                        break;
+                    }
+                    conn.bytes_sent += event.len();
+                }
+            }
+        }
+    }
+
+    // How a client is shown in the history. Anonymous clients get a token
+    // made from their address, the same for every connection it makes.
+    fn client_label(&self, conn: &HttpConnection, addr: &SocketAddr) -> String {
+        if self.minimal_logging || (self.honor_dnt && conn.do_not_track) {
+            return format!("anon-{:08x}", self.log_salt.hash_one(addr.ip()) >> 32);
+        }
+        match addr {
+            SocketAddr::V4(addr) => format!("{}:{}", addr.ip(), addr.port()),
+            SocketAddr::V6(addr) => format!("[{}]:{}", addr.ip(), addr.port()),
+        }
+    }
+
+    // Each request is logged once, with its final status: when its response
+    // is done, or when the connection closes before then. Connections closed
+    // without making a request are logged too.
+    fn log_request(&self, conn: &mut HttpConnection) {
+        if !conn.logged {
+            self.write_conn_to_history(conn);
+            conn.logged = true;
+        }
     }
 
     fn write_conn_to_history(&self, conn: &HttpConnection) {
-        if let Ok(peer_addr) = conn.stream.peer_addr() {
-            let ip_str = match peer_addr {
-                SocketAddr::V4(addr) => format!("{}:{}", addr.ip(), addr.port()),
-                SocketAddr::V6(addr) => format!("[{}]:{}", addr.ip(), addr.port()),
-            };
-            let code_str = match &conn.response {
-                Some(resp) => resp.get_code(),
-                None => "   ".to_string(),
+        if let Ok(peer_addr) = conn.peer_addr() {
+            let ip_str = self.client_label(conn, &peer_addr);
+            let code_str = match (&conn.response, &conn.proxy) {
+                (Some(resp), _) => resp.get_code(),
+                (None, Some(proxy)) => proxy.status.clone().unwrap_or_else(|| "   ".to_string()),
+                (None, None) => "   ".to_string(),
             };
             let path_str = match &conn.last_requested_uri {
                 Some(path) => path,
                 None => "[No path...]",
             };
             let method_str = match &conn.last_requested_method {
-                Some(HttpMethod::GET) => "GET",
-                Some(HttpMethod::HEAD) => "HEAD",
-                Some(HttpMethod::POST) => "POST",
+                Some(method) => method_to_str(method),
                 None => "???",
             };
+            // What content negotiation settled on, e.g. " (text/css, br)"
+            let content_str = match &conn.response {
+                Some(resp) => match (
+                    resp.get_header("Content-Type"),
+                    resp.get_header("Content-Encoding"),
+                ) {
+                    (Some(content_type), Some(encoding)) => {
+                        format!(" ({}, {})", content_type, encoding)
+                    }
+                    (Some(content_type), None) => format!(" ({})", content_type),
+                    _ => String::new(),
+                },
+                None => String::new(),
+            };
             let pb_str = match &conn.post_buffer {
                 Some(pb) => {
+                    let field_names: Vec<&str> =
+                        pb.get_fields().iter().map(|(name, _)| &name[..]).collect();
                     format!(
-                        "{}{}",
+                        "{}{}{}{}",
                         if pb.get_new_files().len() > 0 {
                             " files: "
                         } else {
                             ""
                         },
-                        pb.get_new_files().join(", ")
+                        pb.get_new_files().join(", "),
+                        if !field_names.is_empty() {
+                            " fields: "
+                        } else {
+                            ""
+                        },
+                        field_names.join(", ")
                     )
                 }
                 None => {
@@ -421,15 +1249,43 @@ impl HttpTui<'_> {
                 }
             };
             let _ = self.history_channel.send(format!(
-                "{:<22} {} {:<4} {}{}",
-                ip_str, code_str, method_str, path_str, pb_str
+                "{:<22} {} {:<4} {}{}{}",
+                ip_str, code_str, method_str, path_str, content_str, pb_str
             ));
         }
     }
 
+    fn record_request(&self, conn: &HttpConnection) {
+        let mut stats = match &self.stats {
+            Some(stats) => stats.borrow_mut(),
+            None => {
+                return;
+            }
+        };
+        stats.add_request();
+        // Uploads are counted separately, once their bodies are read
+        let served = match (&conn.response, &conn.last_requested_method) {
+            (Some(resp), Some(HttpMethod::GET)) | (Some(resp), Some(HttpMethod::HEAD)) => {
+                resp.get_code().starts_with('2')
+            }
+            _ => false,
+        };
+        if let (true, Some(path)) = (served, &conn.last_requested_uri) {
+            stats.add_path(path);
+        }
+    }
+
+    // Only called once the whole body has been read, so that each file is
+    // counted once.
+    fn record_uploads(&self, conn: &HttpConnection) {
+        if let (Some(stats), Some(pb)) = (&self.stats, &conn.post_buffer) {
+            stats.borrow_mut().add_uploads(pb.get_new_files());
+        }
+    }
+
     fn handle_request(&self, conn: &mut HttpConnection) -> Result<ConnectionState, io::Error> {
         let res = self.parse_and_service_request(conn);
-        self.write_conn_to_history(conn);
+        self.record_request(conn);
 
         let state = match res {
             Ok(state) => state,
@@ -445,6 +1301,10 @@ impl HttpTui<'_> {
             }
         };
 
+        if state != ConnectionState::ReadingPostBody {
+            self.record_uploads(conn);
+        }
+
         if state == ConnectionState::WritingResponse {
             // Force an initial write of the data
             self.write_partial_final_response(conn)
@@ -457,8 +1317,12 @@ impl HttpTui<'_> {
         &self,
         conn: &mut HttpConnection,
     ) -> Result<ConnectionState, io::Error> {
+        if conn.buffer.len() < self.max_header_size {
+            conn.buffer.resize(self.max_header_size, 0);
+        }
+        let limit = self.max_header_size;
         let buffer = &mut conn.buffer;
-        let bytes_read = match conn.stream.read(&mut buffer[conn.bytes_read..]) {
+        let bytes_read = match conn.stream.read(&mut buffer[conn.bytes_read..limit]) {
             Ok(size) => size,
             Err(_err) => {
                 /*
@@ -475,26 +1339,39 @@ impl HttpTui<'_> {
         conn.bytes_read += bytes_read;
         if bytes_read == 0 {
             return Ok(ConnectionState::Closing);
-        } else if conn.bytes_read == buffer.len() {
-            if let Some(start) = boyer_moore::find_body_start(&conn.buffer[..conn.bytes_read]) {
-                conn.body_start_location = start;
-                return self.handle_request(conn);
-            }
+        }
+
+        // Catch a garbage method as soon as it arrives, rather than once the
+        // buffer fills up.
+        if let Err(status) = check_method_token(&conn.buffer[..conn.bytes_read]) {
+            return self.create_oneoff_response(
+                status,
+                conn,
+                Some("Invalid request method.".to_string()),
+            );
+        }
+
+        // The delimiter may straddle the previous read, so back up by one
+        // byte less than its length.
+        let scan_start = conn.scan_from.saturating_sub(3);
+        if let Some(start) = boyer_moore::find_body_start(&conn.buffer[scan_start..conn.bytes_read])
+        {
+            conn.body_start_location = scan_start + start;
+            return self.handle_request(conn);
+        }
+        conn.scan_from = conn.bytes_read;
+
+        if conn.bytes_read == limit {
             return self.create_oneoff_response(
                 HttpStatus::RequestHeadersTooLarge,
                 conn,
-                Some(
-                    "Request headers are too long. The total size must be less than 4KB."
-                        .to_string(),
-                ),
+                Some(format!(
+                    "Request headers are too long. The total size must be less than {} bytes.",
+                    limit
+                )),
             );
-        } else {
-            if let Some(start) = boyer_moore::find_body_start(&conn.buffer[..conn.bytes_read]) {
-                conn.body_start_location = start;
-                return self.handle_request(conn);
-            }
-            Ok(ConnectionState::ReadingRequest)
         }
+        Ok(ConnectionState::ReadingRequest)
     }
 
     fn handle_post(
@@ -502,20 +1379,105 @@ impl HttpTui<'_> {
         req: &HttpRequest,
         conn: &mut HttpConnection,
     ) -> Result<HttpResult, io::Error> {
-        if !self.uploading {
-            return Ok(HttpResult::Error(
-                HttpStatus::MethodNotAllowed,
-                Some(format!("This server does not accept POST requests.")),
-            ));
-        }
-
         // Returning an error in this function is questionable.
         // Any browser making a real POST request will have its connection
         // reset while sending its data over. They will receive the error
         // message, but probably won't display it.
 
+        // Checked when the request was parsed
+        let coding = get_transfer_coding(req).unwrap_or(TransferCoding::None);
+
+        // Without either, the request has no body as far as HTTP is
+        // concerned, and reading one until the client closes the connection
+        // would be guessing.
+        if req.get_header("content-length").is_none() && coding == TransferCoding::None {
+            // Anything sent after the head cannot be told apart from the
+            // next request
+            conn.keep_alive = false;
+            return Ok(HttpResult::Error(
+                HttpStatus::LengthRequired,
+                Some("Uploads must give the Content-Length of their body.".to_string()),
+            ));
+        }
+
+        let gzip = match req.get_header("content-encoding") {
+            Some(encoding) if self.decompress_uploads && encoding != "identity" => {
+                // Forms would have to be decompressed before they are parsed
+                let gzip = encoding.eq_ignore_ascii_case("gzip")
+                    || encoding.eq_ignore_ascii_case("x-gzip");
+                if !gzip || is_multipart(req) {
+                    return Ok(HttpResult::Error(
+                        HttpStatus::UnsupportedMediaType,
+                        Some(
+                            "Only raw uploads compressed with gzip can be decompressed."
+                                .to_string(),
+                        ),
+                    ));
+                }
+                true
+            }
+            _ => false,
+        };
+        if coding == TransferCoding::GzipChunked {
+            // Forms would have to be decompressed before they are parsed, and
+            // a body can only be decompressed once
+            if gzip || is_multipart(req) {
+                return Ok(HttpResult::Error(
+                    HttpStatus::NotImplemented,
+                    Some(
+                        "Only raw uploads without a Content-Encoding can be sent with a gzip \
+                         Transfer-Encoding."
+                            .to_string(),
+                    ),
+                ));
+            }
+        }
+        let gzip = gzip || coding == TransferCoding::GzipChunked;
+
+        // A body declared too large can be refused before allocating a buffer
+        // or reading any of it. For forms this counts the few hundred bytes
+        // of multipart framing too. Compressed bodies are limited by what
+        // they expand to.
+        if let Some(length) = get_content_length(req) {
+            if !gzip && self.upload_size_limit > 0 && length > self.upload_size_limit {
+                return Ok(HttpResult::Error(
+                    HttpStatus::PayloadTooLarge,
+                    Some(format!(
+                        "Upload size limit of {} bytes exceeded",
+                        self.upload_size_limit
+                    )),
+                ));
+            }
+        }
+
+        let echo = self.debug_echo && req.path == DEBUG_ECHO_PATH;
+
         let boundary = match get_post_boundary(req) {
             Some(b) => b,
+            None if echo => {
+                return Ok(HttpResult::Error(
+                    HttpStatus::UnsupportedMediaType,
+                    Some("Only multipart forms can be echoed.".to_string()),
+                ));
+            }
+            None if !is_multipart(req) => {
+                // Not a form, so the body (if there is one) is the file itself.
+                let length = get_content_length(req);
+                if length.is_some() || coding != TransferCoding::None {
+                    return self.handle_raw_post(req, conn, length, gzip);
+                }
+                return Ok(HttpResult::Error(
+                    HttpStatus::BadRequest,
+                    Some(format!(
+                        "Failed to find a multipart boundary or a Content-Length for a raw \
+                         upload: {}",
+                        match req.get_header("content-type") {
+                            Some(ct) => ct,
+                            None => "[ Missing ]",
+                        }
+                    )),
+                ));
+            }
             None => {
                 return Ok(HttpResult::Error(
                     HttpStatus::BadRequest,
@@ -552,38 +1514,276 @@ impl HttpTui<'_> {
 
         let path = self.root_dir.join(normalized_path);
 
-        let canonical_path = match get_and_check_canon_path(&self.root_dir, path)? {
-            Some(path) => path,
-            None => {
-                return Ok(HttpResult::Error(
-                    HttpStatus::NotFound,
-                    Some("Path disallowed.".to_string()),
-                ));
-            }
-        };
-
-        let pb = PostBuffer::new(
-            canonical_path,
-            post_delimeter,
+        // Nothing is written when echoing, so there is no directory to check
+        let canonical_path = if echo {
+            self.root_dir.to_path_buf()
+        } else {
+            match get_and_check_canon_path(&self.root_dir, path, self.follow_symlinks)? {
+                Some(path) => path,
+                None => {
+                    return Ok(HttpResult::Error(
+                        HttpStatus::NotFound,
+                        Some("Path disallowed.".to_string()),
+                    ));
+                }
+            }
+        };
+
+        let mut pb = PostBuffer::new(
+            canonical_path,
+            post_delimeter,
             real_boundary,
-            &conn.buffer[conn.body_start_location..conn.bytes_read],
+            conn.initial_body(),
             self.upload_size_limit,
+            self.discard_limit,
+            self.forced_upload_extension.clone(),
         );
+        pb.set_plus_as_space(self.plus_as_space);
+        pb.set_dry_run(echo);
+        if coding != TransferCoding::None && pb.set_chunked().is_err() {
+            conn.keep_alive = false;
+            return Ok(HttpResult::Error(
+                HttpStatus::BadRequest,
+                Some("Malformed chunked body.".to_string()),
+            ));
+        }
 
         conn.post_buffer = Some(pb);
         Ok(HttpResult::ReadRequestBody)
     }
 
+    fn handle_raw_post(
+        &self,
+        req: &HttpRequest,
+        conn: &mut HttpConnection,
+        // None for a chunked body
+        length: Option<usize>,
+        gzip: bool,
+    ) -> Result<HttpResult, io::Error> {
+        let normalized_path = if req.path.starts_with("/") {
+            &req.path[1..]
+        } else {
+            &req.path[..]
+        };
+
+        // The last component of the path names the new file. It must be
+        // created inside a directory that already exists.
+        let relative_path = Path::new(normalized_path);
+        let filename = match relative_path.file_name().and_then(|f| f.to_str()) {
+            Some(f) if !normalized_path.ends_with('/') => f,
+            _ => {
+                return Ok(HttpResult::Error(
+                    HttpStatus::BadRequest,
+                    Some("Raw uploads must be sent to the path of the new file.".to_string()),
+                ));
+            }
+        };
+        let parent = relative_path.parent().unwrap_or(Path::new(""));
+
+        let path = self.root_dir.join(parent);
+
+        let canonical_path =
+            match get_and_check_canon_path(self.root_dir, path, self.follow_symlinks)? {
+                Some(path) => path,
+                None => {
+                    return Ok(HttpResult::Error(
+                        HttpStatus::NotFound,
+                        Some("Path disallowed.".to_string()),
+                    ));
+                }
+            };
+
+        let mut pb = PostBuffer::new_raw(
+            canonical_path,
+            filename.to_string(),
+            length.unwrap_or(usize::MAX),
+            conn.initial_body(),
+            self.upload_size_limit,
+            self.discard_limit,
+            self.forced_upload_extension.clone(),
+        );
+        pb.set_gzip(gzip);
+        if length.is_none() && pb.set_chunked().is_err() {
+            conn.keep_alive = false;
+            return Ok(HttpResult::Error(
+                HttpStatus::BadRequest,
+                Some("Malformed chunked body.".to_string()),
+            ));
+        }
+
+        conn.post_buffer = Some(pb);
+        Ok(HttpResult::ReadRequestBody)
+    }
+
+    fn create_text_response(
+        &self,
+        req: &HttpRequest,
+        body: String,
+        content_type: &str,
+    ) -> HttpResult {
+        let mut resp = HttpResponse::new(HttpStatus::OK, &req.version);
+        resp.add_header("Server".to_string(), "hypershare".to_string());
+        resp.add_header("Content-Type".to_string(), content_type.to_string());
+
+        let len = body.len();
+        resp.set_content_length(len);
+        resp.add_body(ResponseDataType::String(SeekableString::new(body)));
+
+        HttpResult::Response(resp, len)
+    }
+
+    // Serves the built-in defaults for well-known paths. A real file in the
+    // root always takes precedence.
+    fn handle_reserved_path(&self, req: &HttpRequest) -> Option<HttpResult> {
+        let (body, content_type) = match req.path.as_str() {
+            ROBOTS_TXT_PATH => (self.robots_txt.clone()?, "text/plain; charset=utf-8"),
+            SECURITY_TXT_PATH => (self.security_txt.clone()?, "text/plain; charset=utf-8"),
+            DOWNLOADS_PATH => (
+                self.downloads.as_ref()?.borrow().to_json(),
+                "application/json",
+            ),
+            _ => {
+                return None;
+            }
+        };
+
+        if let Ok(Some(_)) = self.find_in_roots(&req.path[1..]) {
+            return None;
+        }
+
+        Some(self.create_text_response(req, body, content_type))
+    }
+
+    // POST /__admin/toggle flips the server between serving and rejecting
+    // requests, like the toggle key in the interface. It works while the
+    // server is disabled, so that it can be turned back on.
+    fn handle_admin_toggle(
+        &self,
+        req: &HttpRequest,
+        conn: &mut HttpConnection,
+    ) -> Result<ConnectionState, io::Error> {
+        // The body is never read
+        if has_body(req) {
+            conn.keep_alive = false;
+        }
+
+        if req.method != Some(HttpMethod::POST) {
+            return self.create_oneoff_response_with_headers(
+                HttpStatus::MethodNotAllowed,
+                conn,
+                Some("The server can only be toggled with POST.".to_string()),
+                vec![("Allow".to_string(), "POST".to_string())],
+            );
+        }
+
+        // Scheme names are case-insensitive, the credentials are not
+        let authorized = match (req.get_header("authorization"), &self.admin_authorization) {
+            (Some(given), Some(expected)) => {
+                let (given, expected) = (given.as_bytes(), expected.as_bytes());
+                given.len() == expected.len()
+                    && given[..6].eq_ignore_ascii_case(b"basic ")
+                    && constant_time_eq(&given[6..], &expected[6..])
+            }
+            _ => false,
+        };
+        if !authorized {
+            return self.create_oneoff_response_with_headers(
+                HttpStatus::Unauthorized,
+                conn,
+                Some("Valid admin credentials are required.".to_string()),
+                vec![(
+                    "WWW-Authenticate".to_string(),
+                    "Basic realm=\"hypershare admin\"".to_string(),
+                )],
+            );
+        }
+
+        let message = if self.toggle_disabled() {
+            "Server disabled."
+        } else {
+            "Server enabled."
+        };
+        let _ = self.history_channel.send(format!(
+            "{} by {}",
+            message,
+            conn.peer_addr()
+                .map(|addr| self.client_label(conn, &addr))
+                .unwrap_or_else(|_| "[unknown]".to_string())
+        ));
+        self.create_oneoff_response(HttpStatus::OK, conn, Some(message.to_string()))
+    }
+
+    fn count_download(&self, canonical: &Path) {
+        if let Some(downloads) = &self.downloads {
+            if let Err(e) = downloads.borrow_mut().increment(canonical) {
+                let _ = self
+                    .history_channel
+                    .send(format!("Could not save download counts: {}", e));
+            }
+        }
+    }
+
+    fn create_event_stream_response(&self, req: &HttpRequest) -> HttpResult {
+        let mut resp = HttpResponse::new(HttpStatus::OK, &req.version);
+        resp.add_header("Server".to_string(), "hypershare".to_string());
+        resp.add_header("Content-Type".to_string(), "text/event-stream".to_string());
+        resp.add_header("Cache-Control".to_string(), "no-cache".to_string());
+        if req.version == HttpVersion::Http1_1 {
+            resp.set_chunked();
+        }
+        HttpResult::EventStream(resp)
+    }
+
+    // Every path supports the same methods, so `OPTIONS *` (asking about the
+    // server as a whole) and `OPTIONS /path` get the same answer.
+    fn handle_options(&self, req: &HttpRequest) -> HttpResult {
+        let mut resp = HttpResponse::new(HttpStatus::OK, &req.version);
+        resp.add_header("Server".to_string(), "hypershare".to_string());
+        resp.add_header("Allow".to_string(), allow_header(&self.allowed_methods));
+        resp.set_content_length(0);
+        HttpResult::Response(resp, 0)
+    }
+
+    // Look `relative` up in the served directory, then each --root, giving
+    // the joined and canonical paths from the first that has it. Each is
+    // kept to its own root. When none has it, the served directory's answer
+    // is given, so errors are the same as with a single root.
+    fn find_in_roots(&self, relative: &str) -> Result<Option<(PathBuf, PathBuf)>, io::Error> {
+        let mut first = None;
+        let roots = std::iter::once(self.root_dir).chain(self.fallback_roots.iter().map(|r| &**r));
+        for root in roots {
+            let path = root.join(relative);
+            match get_and_check_canon_path(root, path.clone(), self.follow_symlinks) {
+                Ok(Some(canonical_path)) => {
+                    return Ok(Some((path, canonical_path)));
+                }
+                other => {
+                    if first.is_none() {
+                        first = Some(other.map(|_| None));
+                    }
+                }
+            }
+        }
+        first.unwrap_or(Ok(None))
+    }
+
     fn handle_get(&self, req: &HttpRequest) -> Result<HttpResult, io::Error> {
+        if let Some(result) = self.handle_reserved_path(req) {
+            return Ok(result);
+        }
+
+        if req.path == EVENTS_PATH && self.watcher.is_some() {
+            return Ok(self.create_event_stream_response(req));
+        }
+
         let normalized_path = if req.path.starts_with("/") {
             &req.path[1..]
         } else {
             &req.path[..]
         };
 
-        let path = self.root_dir.join(normalized_path);
-        let mut canonical_path = match get_and_check_canon_path(&self.root_dir, path)? {
-            Some(path) => path,
+        let (path, mut canonical_path) = match self.find_in_roots(normalized_path)? {
+            Some(found) => found,
             None => {
                 return Ok(HttpResult::Error(
                     HttpStatus::NotFound,
@@ -602,13 +1802,23 @@ impl HttpTui<'_> {
             Ok(data) => data,
         };
 
-        if !self.no_append_slash {
+        let is_symlink = fs::symlink_metadata(&path)
+            .map(|meta| meta.file_type().is_symlink())
+            .unwrap_or(false);
+        // A link to one of its own ancestors (like `up -> ..`) can be followed
+        // forever, one redirect deeper each time, so serve it where it is.
+        let skip_slash =
+            is_symlink && (self.no_symlink_slash || is_ancestor_link(&path, &canonical_path));
+        if !(self.no_append_slash || skip_slash) {
             if normalized_path.len() > 0
                 && original_metadata.is_dir()
                 && !normalized_path.ends_with('/')
             {
                 let mut resp = HttpResponse::new(HttpStatus::MovedPermanently, &req.version);
-                resp.add_header("Location".to_string(), format!("/{}/", normalized_path));
+                resp.add_header(
+                    "Location".to_string(),
+                    format!("{}/{}/", self.base_path, normalized_path),
+                );
                 resp.add_header("Server".to_string(), format!("hypershare"));
                 return Ok(HttpResult::Response(resp, 0));
             }
@@ -616,14 +1826,20 @@ impl HttpTui<'_> {
 
         // If we are a directory, attempt to find the index file.
         // If it's not there, just render the directory.
-        let metadata = if original_metadata.is_dir() && !self.no_index_file {
-            canonical_path.push(self.index_file);
+        let mut served_index = false;
+        // `?noindex` shows the listing even when there is an index
+        let no_index = self.no_index_file || req.get_query_param("noindex").is_some();
+        let metadata = if original_metadata.is_dir() && !no_index {
+            canonical_path.push(&self.index_file);
             match fs::metadata(&canonical_path) {
-                Err(_error) => {
+                Ok(data) if self.listing_layout.extensions.allows(&canonical_path) => {
+                    served_index = true;
+                    data
+                }
+                _ => {
                     canonical_path.pop();
                     original_metadata
                 }
-                Ok(data) => data,
             }
         } else {
             original_metadata
@@ -636,58 +1852,275 @@ impl HttpTui<'_> {
             ));
         }
 
-        if !self.dir_listings && metadata.is_dir() {
+        // Files that may not be served look just like missing ones, so that
+        // whether they exist is not given away. Both the name asked for and
+        // the file it leads to must be allowed.
+        let extensions = &self.listing_layout.extensions;
+        if metadata.is_file() && !(extensions.allows(&path) && extensions.allows(&canonical_path)) {
+            return Ok(not_found());
+        }
+
+        // Listings have no ETag, as they change whenever any entry does
+        let etag = if metadata.is_file() {
+            generate_etag(&metadata)
+        } else {
+            None
+        };
+
+        if let (Some(if_match), Some(etag)) = (req.get_header("if-match"), &etag) {
+            if !etag_matches(if_match, etag) {
+                return Ok(HttpResult::Error(
+                    HttpStatus::PreconditionFailed,
+                    Some("The file does not match If-Match.".to_string()),
+                ));
+            }
+        }
+
+        let listing_allowed =
+            self.dir_listings && !(self.no_root_listing && normalized_path.is_empty());
+        if !listing_allowed && metadata.is_dir() {
             return Ok(HttpResult::Error(
                 HttpStatus::PermissionDenied,
                 Some(format!("Unable to list this directory.")),
             ));
         }
 
-        let (mut response_data, full_length, mime) = if metadata.is_dir() {
-            let s: String = rendering::render_directory(
-                normalized_path,
-                canonical_path.as_path(),
-                self.uploading,
-            );
-            let len = s.len();
-            let data = ResponseDataType::String(SeekableString::new(s));
-            (data, len, Some("text/html; charset=utf-8"))
+        // Listings are rendered as HTML, or as a plain list of names for
+        // clients that ask for text/plain
+        let listing_type = if metadata.is_dir() {
+            match mime::choose_type(
+                req.get_header("accept").map(|s| s.as_str()),
+                &["text/html; charset=utf-8", "text/plain; charset=utf-8"],
+            ) {
+                Some(listing_type) => Some(listing_type),
+                None => {
+                    return Ok(HttpResult::Error(
+                        HttpStatus::NotAcceptable,
+                        Some(
+                            "Directory listings are only available as HTML or plain text."
+                                .to_string(),
+                        ),
+                    ));
+                }
+            }
         } else {
-            let data = ResponseDataType::File(fs::File::open(&canonical_path)?);
-            let len = if metadata.is_file() {
-                metadata.len() as usize
+            None
+        };
+
+        let mut unknown_type = false;
+        let (response_data, full_length, content_type) =
+            if listing_type == Some("text/plain; charset=utf-8") {
+                let s = rendering::render_directory_text(
+                    canonical_path.as_path(),
+                    &self.listing_layout.extensions,
+                );
+                let len = s.len();
+                let data = ResponseDataType::String(SeekableString::new(s));
+                (data, len, "text/plain; charset=utf-8")
+            } else if metadata.is_dir() {
+                // HEAD requests render the listing too. Its length depends on
+                // every entry shown, so there is no cheaper way to report the
+                // same Content-Length a GET would get; pagination keeps the cost
+                // bounded.
+                let page = req
+                    .get_query_param("page")
+                    .and_then(|page| page.parse::<usize>().ok())
+                    .filter(|page| *page > 0)
+                    .unwrap_or(1);
+                let per_page = req
+                    .get_query_param("per")
+                    .and_then(|per| per.parse::<usize>().ok())
+                    .filter(|per| *per > 0)
+                    .map(|per| min(per, rendering::MAX_PAGE_SIZE))
+                    .unwrap_or(rendering::DEFAULT_PAGE_SIZE);
+                // Download counts change without the directory changing, so
+                // listings showing them are always rendered
+                let cache = self
+                    .listing_cache
+                    .as_ref()
+                    .filter(|_| !self.listing_layout.columns.contains(&Column::Downloads));
+                let mtime = metadata.modified().ok();
+                let cached = match (cache, mtime) {
+                    (Some(cache), Some(mtime)) => {
+                        cache
+                            .borrow_mut()
+                            .get(&canonical_path, page, per_page, mtime)
+                    }
+                    _ => None,
+                };
+                let s: String = match cached {
+                    Some(s) => s,
+                    None => {
+                        let s = rendering::render_directory(
+                            &self.base_path,
+                            normalized_path,
+                            canonical_path.as_path(),
+                            self.uploading,
+                            page,
+                            per_page,
+                            &self.listing_layout,
+                            self.downloads
+                                .as_ref()
+                                .map(|downloads| downloads.borrow())
+                                .as_deref(),
+                        );
+                        if let (Some(cache), Some(mtime)) = (cache, mtime) {
+                            cache
+                                .borrow_mut()
+                                .insert(&canonical_path, page, per_page, mtime, &s);
+                        }
+                        s
+                    }
+                };
+                let len = s.len();
+                let data = ResponseDataType::String(SeekableString::new(s));
+                (data, len, "text/html; charset=utf-8")
             } else {
-                std::u32::MAX as usize
-            };
-            // (data, len, None)
-            (
-                data,
-                len,
-                if req.path.ends_with(".html") {
-                    Some("text/html; charset=utf-8")
+                let data = ResponseDataType::File(fs::File::open(&canonical_path)?);
+                let len = if metadata.is_file() {
+                    metadata.len() as usize
                 } else {
-                    None
-                },
-            )
+                    std::u32::MAX as usize
+                };
+                let guessed = mime::mime_from_path(&canonical_path);
+                unknown_type = guessed.is_none();
+                (data, len, guessed.unwrap_or(&self.default_mime))
+            };
+
+        // Ranges in units other than bytes are ignored, and the whole file
+        // is sent.
+        let range_header = if self.no_ranges {
+            None
+        } else {
+            req.get_header("range").filter(|r| r.starts_with("bytes="))
         };
 
-        let (start, range, used_range) = match req.get_header("range") {
-            Some(content_range_str) => {
-                if let Some(content_range) = decode_content_range(content_range_str) {
-                    let real_start = min(content_range.start, full_length);
-                    let real_len = match content_range.len {
-                        Some(len) => min(len, full_length - real_start),
-                        None => full_length - real_start,
-                    };
-                    (real_start, real_len, true)
-                } else {
-                    return Ok(HttpResult::Error(
-                        HttpStatus::BadRequest,
-                        Some(format!("Could not decode Range header")),
-                    ));
+        let content_ranges = match range_header.map(|header| decode_content_ranges(header)) {
+            Some(Some(content_ranges)) => content_ranges,
+            Some(None) => {
+                return Ok(HttpResult::Error(
+                    HttpStatus::BadRequest,
+                    Some(format!("Could not decode Range header")),
+                ));
+            }
+            None => Vec::new(),
+        };
+        // The (start, length) of each range, within the body
+        let mut ranges: Vec<(usize, usize)> = content_ranges
+            .iter()
+            .map(|content_range| {
+                let real_start = min(content_range.start, full_length);
+                let real_len = match content_range.len {
+                    Some(len) => min(len, full_length - real_start),
+                    None => full_length - real_start,
+                };
+                (real_start, real_len)
+            })
+            .collect();
+        if ranges.len() > 1 {
+            ranges.retain(|(_, len)| *len > 0);
+        }
+        // Many small ranges, or overlapping ones, would make the response
+        // far bigger than the file, so they get the file instead.
+        if ranges.len() > 1 {
+            let total: usize = ranges.iter().map(|(_, len)| len).sum();
+            if ranges.len() > self.max_ranges || total > self.max_range_bytes.unwrap_or(full_length)
+            {
+                ranges.clear();
+            }
+        }
+        // Several ranges are sent as the parts of a multipart/byteranges
+        // body, which is only done for files. Anything else is sent whole.
+        let multipart = ranges.len() > 1 && metadata.is_file();
+        let (start, range, used_range) = match ranges.len() {
+            1 => (ranges[0].0, ranges[0].1, true),
+            _ if multipart => (0, 0, true),
+            _ => (0, full_length, false),
+        };
+
+        // A range refers to the uncompressed bytes, so only whole responses
+        // are compressed.
+        let compressible = self.compress
+            && compression::should_compress(content_type, full_length, self.compress_min_size);
+        // A precompressed copy is served whatever the type and size, since
+        // someone went to the trouble of making it.
+        let mut available = vec![Encoding::Identity];
+        if self.compress && !used_range {
+            for encoding in &[Encoding::Brotli, Encoding::Gzip] {
+                if compressible
+                    || (metadata.is_file()
+                        && compression::find_sidecar(&canonical_path, *encoding).is_some())
+                {
+                    available.push(*encoding);
                 }
             }
-            None => (0, full_length, false),
+        }
+        let encoding = match compression::choose_encoding(
+            req.get_header("accept-encoding").map(|s| s.as_str()),
+            &available,
+        ) {
+            Some(encoding) => encoding,
+            None => {
+                return Ok(HttpResult::Error(
+                    HttpStatus::NotAcceptable,
+                    Some("None of the accepted encodings are available.".to_string()),
+                ));
+            }
+        };
+        let sidecar = if metadata.is_file() {
+            compression::find_sidecar(&canonical_path, encoding)
+        } else {
+            None
+        };
+        let varies = compressible || (self.compress && compression::has_sidecar(&canonical_path));
+        let (response_data, range, encoding) = match sidecar {
+            Some(sidecar) => {
+                let file = fs::File::open(&sidecar)?;
+                let len = file.metadata()?.len() as usize;
+                (ResponseDataType::File(file), Some(len), encoding)
+            }
+            // Compressing only to measure the result would cost as much as
+            // the GET, so HEAD gets no Content-Length instead.
+            None if compressible
+                && encoding != Encoding::Identity
+                && req.method == Some(HttpMethod::HEAD) =>
+            {
+                (ResponseDataType::None, None, encoding)
+            }
+            None if compressible && encoding != Encoding::Identity => {
+                let compressed = match response_data {
+                    ResponseDataType::String(s) => compression::compress(encoding, s)?,
+                    ResponseDataType::File(file) => compression::compress(encoding, file)?,
+                    ResponseDataType::Bytes(bytes) => compression::compress(encoding, bytes)?,
+                    ResponseDataType::Ranges(ranges) => compression::compress(encoding, ranges)?,
+                    ResponseDataType::None => Vec::new(),
+                };
+                let len = compressed.len();
+                (
+                    ResponseDataType::Bytes(io::Cursor::new(compressed)),
+                    Some(len),
+                    encoding,
+                )
+            }
+            None => (response_data, Some(range), Encoding::Identity),
+        };
+
+        let boundary = format!(
+            "hypershare-{:x}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_nanos())
+                .unwrap_or(0)
+        );
+        // The parts' lengths and the framing around them are all sent, so
+        // the body's length counts both.
+        let (mut response_data, range) = match response_data {
+            ResponseDataType::File(file) if multipart => {
+                let ranges = MultiRange::new(file, &boundary, content_type, &ranges, full_length);
+                let len = ranges.length();
+                (ResponseDataType::Ranges(ranges), Some(len))
+            }
+            response_data => (response_data, range),
         };
 
         let mut resp = HttpResponse::new(
@@ -700,11 +2133,61 @@ impl HttpTui<'_> {
         );
 
         resp.add_header("Server".to_string(), "hypershare".to_string());
-        resp.add_header("Accept-Ranges".to_string(), "bytes".to_string());
+        if !self.no_ranges {
+            resp.add_header("Accept-Ranges".to_string(), "bytes".to_string());
+        }
 
-        resp.set_content_length(range);
+        if let Some(range) = range {
+            resp.set_content_length(range);
+        }
 
-        if used_range {
+        let compressed = encoding != Encoding::Identity;
+        let mut vary = Vec::new();
+        if metadata.is_dir() {
+            vary.push("Accept");
+        }
+        if varies {
+            vary.push("Accept-Encoding");
+        }
+        if !vary.is_empty() {
+            resp.add_header("Vary".to_string(), vary.join(", "));
+        }
+        if compressed {
+            resp.add_header(
+                "Content-Encoding".to_string(),
+                compression::encoding_to_str(encoding).to_string(),
+            );
+        }
+
+        // Name the index file a directory was answered with, for caches.
+        // With --no-slash, relative links in it still resolve against the
+        // parent directory, since browsers only go by the URL they asked for.
+        if served_index {
+            let dir = normalized_path.trim_end_matches('/');
+            let location = if dir.is_empty() {
+                format!("{}/{}", self.base_path, self.index_file)
+            } else {
+                format!("{}/{}/{}", self.base_path, dir, self.index_file)
+            };
+            resp.add_header("Content-Location".to_string(), location);
+        }
+
+        if let Some(etag) = etag {
+            // The compressed bytes are not the file's, so the tag can only
+            // be weak.
+            resp.add_header(
+                "ETag".to_string(),
+                if compressed {
+                    format!("W/{}", etag)
+                } else {
+                    etag
+                },
+            );
+        }
+
+        if used_range && !multipart {
+            // Ranges are never compressed, so their length is known
+            let range = range.unwrap_or(0);
             resp.add_header(
                 "Content-Range".to_string(),
                 format!(
@@ -725,14 +2208,55 @@ impl HttpTui<'_> {
             }
         }
 
-        if let Some(content_type) = mime {
-            // If we want to add a content type, add it
+        if multipart {
+            resp.add_header(
+                "Content-Type".to_string(),
+                format!("multipart/byteranges; boundary={}", boundary),
+            );
+        } else {
             resp.add_header("Content-Type".to_string(), content_type.to_string());
         }
+        if unknown_type && !self.no_auto_nosniff && !self.nosniff {
+            // Without a type, browsers would guess from the contents, and
+            // could decide that an uploaded file is a page or a script.
+            resp.add_header("X-Content-Type-Options".to_string(), "nosniff".to_string());
+        }
 
         resp.add_body(response_data);
 
-        Ok(HttpResult::Response(resp, range))
+        // Only HEAD goes without a length, and it sends no body anyway
+        let range = range.unwrap_or(0);
+        Ok(if metadata.is_file() && !served_index {
+            HttpResult::File(resp, range, canonical_path)
+        } else {
+            HttpResult::Response(resp, range)
+        })
+    }
+
+    fn check_path_limits(&self, path: &str) -> Option<(HttpStatus, String)> {
+        let mut depth = 0;
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            depth += 1;
+            if depth > self.max_path_depth {
+                return Some((
+                    HttpStatus::UriTooLong,
+                    format!(
+                        "Paths can be at most {} directories deep.",
+                        self.max_path_depth
+                    ),
+                ));
+            }
+            if component.len() > self.max_component_length {
+                return Some((
+                    HttpStatus::BadRequest,
+                    format!(
+                        "Names in paths can be at most {} bytes long.",
+                        self.max_component_length
+                    ),
+                ));
+            }
+        }
+        None
     }
 
     fn parse_and_service_request(
@@ -741,12 +2265,11 @@ impl HttpTui<'_> {
     ) -> Result<ConnectionState, io::Error> {
         let head = &mut conn.buffer[..conn.body_start_location];
         conn.num_requests += 1;
+        conn.logged = false;
 
         let req: HttpRequest = match decode_request(head) {
             Ok(r) => r,
             Err(status) => {
-                // Kill the connection if we get invalid data
-                conn.keep_alive = false;
                 return self.create_oneoff_response(
                     status,
                     conn,
@@ -757,11 +2280,60 @@ impl HttpTui<'_> {
 
         conn.last_requested_uri = Some(req.path.to_string());
         conn.last_requested_method = req.method.clone();
+        conn.do_not_track = req.get_header("dnt").is_some_and(|dnt| dnt == "1");
+        conn.json_errors = mime::choose_type(
+            req.get_header("accept").map(|s| s.as_str()),
+            &["text/html; charset=utf-8", "application/json"],
+        ) == Some("application/json");
+
+        let coding = match get_transfer_coding(&req) {
+            Ok(coding) => coding,
+            Err(status) => {
+                // The body cannot be read, so neither can anything after it
+                conn.keep_alive = false;
+                return self.create_oneoff_response(
+                    status,
+                    conn,
+                    Some("This server cannot decode the given Transfer-Encoding.".to_string()),
+                );
+            }
+        };
 
-        if self.disabled {
+        // A body framed two different ways could be interpreted differently
+        // by a proxy in front of us, allowing requests to be smuggled.
+        if coding != TransferCoding::None && req.get_header("content-length").is_some() {
+            return self.create_oneoff_response(
+                HttpStatus::BadRequest,
+                conn,
+                Some(
+                    "Requests must not specify both Content-Length and Transfer-Encoding."
+                        .to_string(),
+                ),
+            );
+        }
+
+        // 100-continue is the only expectation there is to meet
+        if let Some(expect) = req.get_header("expect") {
+            if !expect.eq_ignore_ascii_case("100-continue") {
+                if has_body(&req) {
+                    conn.keep_alive = false;
+                }
+                return self.create_oneoff_response(
+                    HttpStatus::ExpectationFailed,
+                    conn,
+                    Some("This server cannot meet the given expectation.".to_string()),
+                );
+            }
+        }
+
+        if req.path == ADMIN_TOGGLE_PATH && self.admin_authorization.is_some() {
+            return self.handle_admin_toggle(&req, conn);
+        }
+
+        if self.disabled.load(Ordering::Acquire) {
             conn.keep_alive = false;
             return self.create_oneoff_response(
-                HttpStatus::ServiceUnavailable,
+                self.disabled_status,
                 conn,
                 Some(
                     "This server has been temporarily disabled. Please contact the administrator \
@@ -778,22 +2350,131 @@ impl HttpTui<'_> {
             None => false,
         };
 
+        // Paths that no file could have are turned away before they reach
+        // the filesystem, where they would only cost work and confusing
+        // errors.
+        if let Some((status, reason)) = self.check_path_limits(&req.path) {
+            if has_body(&req) {
+                conn.keep_alive = false;
+            }
+            return self.create_oneoff_response(status, conn, Some(reason));
+        }
+
+        // Every path is resolved under the root, so once it is gone (deleted
+        // or renamed) every request would fail in a different confusing way.
+        if !self.root_dir.is_dir() {
+            let _ = self.history_channel.send(format!(
+                "Served directory {} no longer exists",
+                self.root_dir.display()
+            ));
+            return self.create_oneoff_response(
+                HttpStatus::ServerError,
+                conn,
+                Some("The directory being served no longer exists.".to_string()),
+            );
+        }
+
+        if let Some(authorizer) = &self.authorizer {
+            // Without a peer address there is nothing to authorize against.
+            let allowed = match conn.peer_addr() {
+                Ok(addr) => authorizer(&req, &addr),
+                Err(_) => false,
+            };
+            if !allowed {
+                if has_body(&req) {
+                    conn.keep_alive = false;
+                }
+                return self.create_oneoff_response(
+                    HttpStatus::PermissionDenied,
+                    conn,
+                    Some("You are not allowed to make this request.".to_string()),
+                );
+            }
+        }
+
+        if let Some(method) = &req.method {
+            for route in &self.proxies {
+                if let Some(path) = route.upstream_path(&req.path) {
+                    if !self.proxy_methods.contains(method) {
+                        if has_body(&req) {
+                            conn.keep_alive = false;
+                        }
+                        return self.create_oneoff_response_with_headers(
+                            HttpStatus::MethodNotAllowed,
+                            conn,
+                            Some(format!(
+                                "This server does not accept {} requests.",
+                                method_to_str(method)
+                            )),
+                            vec![("Allow".to_string(), allow_header(&self.proxy_methods))],
+                        );
+                    }
+                    conn.last_requested_method = Some(method.clone());
+                    return self.start_proxy(&req, conn, route, &path);
+                }
+            }
+        }
+
+        // GET and HEAD bodies are never read, so what is left of one would
+        // be taken as the start of the next request.
+        let bodiless = matches!(req.method, Some(HttpMethod::GET) | Some(HttpMethod::HEAD));
+        if bodiless && has_body(&req) {
+            if self.reject_get_bodies {
+                return self.create_oneoff_response(
+                    HttpStatus::BadRequest,
+                    conn,
+                    Some("GET and HEAD requests must not have a body.".to_string()),
+                );
+            }
+            conn.keep_alive = false;
+        }
+
+        let cgi_script = self.find_cgi_script(&req);
         let maybe_result = match req.method {
             None => {
+                // The body is never read, so it would be taken as the start
+                // of the next request.
+                if has_body(&req) {
+                    conn.keep_alive = false;
+                }
                 return self.create_oneoff_response(
                     HttpStatus::NotImplemented,
                     conn,
                     Some("This server does not implement the requested HTTP method.".to_string()),
                 );
             }
+            // Echoing stores nothing, so it does not need uploads on
+            Some(HttpMethod::POST) if self.debug_echo && req.path == DEBUG_ECHO_PATH => {
+                self.handle_post(&req, conn)
+            }
+            // Scripts can take POSTs even when uploads are off
+            Some(ref method) if cgi_script.is_some() && self.cgi_methods.contains(method) => {
+                return self.start_cgi(&req, conn, cgi_script.as_ref().unwrap());
+            }
+            Some(ref method) if !self.allowed_methods.contains(method) => {
+                if has_body(&req) {
+                    conn.keep_alive = false;
+                }
+                return self.create_oneoff_response_with_headers(
+                    HttpStatus::MethodNotAllowed,
+                    conn,
+                    Some(format!(
+                        "This server does not accept {} requests.",
+                        method_to_str(method)
+                    )),
+                    vec![("Allow".to_string(), allow_header(&self.allowed_methods))],
+                );
+            }
             Some(HttpMethod::GET) => self.handle_get(&req),
             Some(HttpMethod::HEAD) => self.handle_get(&req),
             Some(HttpMethod::POST) => self.handle_post(&req, conn),
+            Some(HttpMethod::OPTIONS) => Ok(self.handle_options(&req)),
         };
         let result = match maybe_result {
             // Attempt to convert the system error into an HTTP error
             // that we can send back to the user.
             Ok(r) => r,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => not_found(),
             Err(error) => match resolve_io_error(&error) {
                 Some(http_error) => HttpResult::Error(http_error, Some(error.to_string())),
                 None => {
@@ -802,16 +2483,49 @@ impl HttpTui<'_> {
             },
         };
 
-        let (mut resp, range) = match result {
-            HttpResult::Error(http_status, msg) => {
-                return self.create_oneoff_response(http_status, conn, msg);
-            }
-            HttpResult::ReadRequestBody => {
-                return self.check_partial_post_body_initial(&req, conn);
-            }
-            HttpResult::Response(resp, range) => (resp, range),
-        };
+        let (mut resp, range, streaming, file) = match result {
+            HttpResult::Error(http_status, msg) => {
+                return self.create_oneoff_response(http_status, conn, msg);
+            }
+            HttpResult::ReadRequestBody => {
+                return self.check_partial_post_body_initial(&req, conn);
+            }
+            HttpResult::Response(resp, range) => (resp, range, false, None),
+            HttpResult::File(resp, range, file) => (resp, range, false, Some(file)),
+            HttpResult::EventStream(resp) => (resp, 0, true, None),
+        };
+
+        // Only whole files fetched with GET count as downloads
+        if self.downloads.is_some()
+            && req.method == Some(HttpMethod::GET)
+            && resp.get_status() == HttpStatus::OK
+        {
+            conn.download = file;
+        }
+
+        let is_head = req.method.unwrap_or(HttpMethod::HEAD) == HttpMethod::HEAD;
+        self.send_response_headers(conn, &mut resp, is_head)?;
+
+        conn.response = Some(resp);
+        if !is_head {
+            conn.bytes_requested += range;
+        }
+
+        if streaming && !is_head {
+            return Ok(ConnectionState::StreamingEvents);
+        }
 
+        Ok(ConnectionState::WritingResponse)
+    }
+
+    // Send the headers of `resp`. The body is written as the connection
+    // becomes writable.
+    fn send_response_headers(
+        &self,
+        conn: &mut HttpConnection,
+        resp: &mut HttpResponse,
+        is_head: bool,
+    ) -> Result<(), io::Error> {
         resp.add_header(
             "Connection".to_string(),
             if conn.keep_alive {
@@ -821,23 +2535,21 @@ impl HttpTui<'_> {
             },
         );
 
+        self.add_common_headers(resp);
+
         // Write headers
-        resp.write_headers_to_stream(&conn.stream)?;
+        resp.write_headers_to_stream(&mut conn.stream)?;
 
         // If method is HEAD, remove the response body
-        if req.method.unwrap_or(HttpMethod::HEAD) == HttpMethod::HEAD {
+        if is_head {
             resp.clear_body();
         }
-
-        conn.response = Some(resp);
-        conn.bytes_requested += range;
-
-        Ok(ConnectionState::WritingResponse)
+        Ok(())
     }
 
     fn write_continue(&self, conn: &mut HttpConnection) -> Result<(), io::Error> {
         let mut resp = HttpResponse::new(HttpStatus::Continue, &HttpVersion::Http1_1);
-        resp.write_headers_to_stream(&conn.stream)?;
+        resp.write_headers_to_stream(&mut conn.stream)?;
         Ok(())
     }
 
@@ -847,6 +2559,10 @@ impl HttpTui<'_> {
     ) -> Result<ConnectionState, io::Error> {
         let done = self.write_partial_response(conn)?;
         if done {
+            if let Some(path) = conn.download.take() {
+                self.count_download(&path);
+            }
+            self.log_request(conn);
             if conn.keep_alive {
                 // Reset the data associated with this connection
                 conn.reset();
@@ -862,15 +2578,45 @@ impl HttpTui<'_> {
     fn write_partial_response(&self, conn: &mut HttpConnection) -> Result<bool, io::Error> {
         Ok(match &mut conn.response {
             Some(ref mut resp) => {
-                let amt_written = resp.partial_write_to_stream(&conn.stream)?;
+                let started = Instant::now();
+                let amt_written = match resp.partial_write_to_stream(&mut conn.stream) {
+                    Ok(amt) => amt,
+                    // A non-blocking stream has no room yet. Nothing was
+                    // written, and the rest is sent once select() says it can
+                    // be. A blocking socket only gives up once --write-timeout
+                    // has passed, and is closed like any other that fails
+                    // rather than blocking everyone else for another one.
+                    Err(error)
+                        if error.kind() == io::ErrorKind::WouldBlock
+                            && !self.write_timed_out(started) =>
+                    {
+                        return Ok(false);
+                    }
+                    Err(error) => {
+                        return Err(error);
+                    }
+                };
                 conn.bytes_sent += amt_written;
-                // If we wrote nothing, we are done
-                amt_written == 0 || conn.bytes_sent >= conn.bytes_requested
+                if let Some(stats) = &self.stats {
+                    stats.borrow_mut().add_bytes(amt_written);
+                }
+                // The byte counts run across every request on the connection
+                // (for the display), so only this response can say whether it
+                // is done. Writing nothing without an error means the body
+                // ran out, so we are done then too.
+                amt_written == 0 || resp.is_finished()
             }
             None => true,
         })
     }
 
+    fn write_timed_out(&self, started: Instant) -> bool {
+        match self.write_timeout {
+            Some(timeout) => started.elapsed() >= timeout,
+            None => false,
+        }
+    }
+
     fn create_http_connection(stream: TcpStream) -> HttpConnection { HttpConnection::new(stream) }
 
     fn handle_conn_sigpipe(&self, conn: &mut HttpConnection) -> Result<(), io::Error> {
@@ -881,6 +2627,8 @@ impl HttpTui<'_> {
                     io::ErrorKind::BrokenPipe => Ok(()),
                     io::ErrorKind::ConnectionReset => Ok(()),
                     io::ErrorKind::ConnectionAborted => Ok(()),
+                    // A read or write outlasted its socket timeout
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Ok(()),
                     // Forward the error if it isn't one of the above
                     _ => Err(error),
                 }
@@ -896,21 +2644,30 @@ impl HttpTui<'_> {
     ) -> Result<ConnectionState, io::Error> {
         let pb = &mut conn.post_buffer.as_mut().unwrap();
 
+        // A small body is not worth the round trip, so it is just read. A
+        // body of unknown length might be any size.
+        let worth_continue = match get_content_length(req) {
+            Some(length) => length > self.continue_threshold,
+            None => true,
+        };
         if req.version == HttpVersion::Http1_1
-            && req.get_header("expect").unwrap_or(&"".to_string()) == "100-continue"
+            && req.get_header("expect").is_some()
+            && worth_continue
         {
+            // Some clients send the body without waiting for a 100
+            // Continue. Whatever of it came with the headers is handled
+            // first, and such clients have no use for the 100.
+            let body_started = pb.has_buffered_data();
             // Call handle_new_data directly so that errors are not
             // suppressed.
             match pb.handle_new_data() {
                 Ok(done) => {
                     if done {
-                        self.create_oneoff_response(
-                            HttpStatus::Created,
-                            conn,
-                            Some(format!("File received.")),
-                        )
+                        self.finish_post(conn)
                     } else {
-                        self.write_continue(conn)?;
+                        if !body_started {
+                            self.write_continue(conn)?;
+                        }
                         Ok(ConnectionState::ReadingPostBody)
                     }
                 }
@@ -931,19 +2688,41 @@ impl HttpTui<'_> {
         }
     }
 
+    fn finish_post(&self, conn: &mut HttpConnection) -> Result<ConnectionState, io::Error> {
+        let pb = conn.post_buffer.as_ref().unwrap();
+        if !pb.is_dry_run() {
+            return self.create_oneoff_response(
+                HttpStatus::Created,
+                conn,
+                Some(format!("File received.")),
+            );
+        }
+        let summary = render_upload_parts(pb.get_parts());
+        self.create_response(
+            HttpStatus::OK,
+            conn,
+            summary,
+            "text/plain; charset=utf-8",
+            vec![],
+        )
+    }
+
     fn check_partial_post_body(
         &self,
         conn: &mut HttpConnection,
     ) -> Result<ConnectionState, io::Error> {
         let pb = &mut conn.post_buffer.as_mut().unwrap();
-        match pb.handle_new_data_queue_error() {
+        // Rejecting right away closes the connection with the rest of the
+        // body unread, so clients see a reset rather than the error message.
+        let result = if self.fast_reject_uploads {
+            pb.handle_new_data()
+        } else {
+            pb.handle_new_data_queue_error()
+        };
+        match result {
             Ok(done) => {
                 if done {
-                    self.create_oneoff_response(
-                        HttpStatus::Created,
-                        conn,
-                        Some(format!("File received.")),
-                    )
+                    self.finish_post(conn)
                 } else {
                     Ok(ConnectionState::ReadingPostBody)
                 }
@@ -969,6 +2748,18 @@ impl HttpTui<'_> {
         if let Some(pb) = &mut conn.post_buffer {
             let bytes_read = match pb.read_into_buffer(&mut conn.stream) {
                 Ok(size) => size,
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    // The chunks cannot be followed, so neither can the rest
+                    // of the connection
+                    let _ = pb.remove_current_file();
+                    conn.keep_alive = false;
+                    let res = self.create_oneoff_response(
+                        HttpStatus::BadRequest,
+                        conn,
+                        Some("Malformed chunked body.".to_string()),
+                    );
+                    return res;
+                }
                 Err(_err) => {
                     // Even though the server has run into a problem, because it is
                     // a problem inherent to the socket connection, we return Ok
@@ -984,16 +2775,14 @@ impl HttpTui<'_> {
                     conn,
                     Some("An error occurred while receiving your file.".to_string()),
                 );
-                let _ = self.write_conn_to_history(conn);
+                self.record_uploads(conn);
                 return res;
             }
 
             let res = self.check_partial_post_body(conn);
             match res {
                 Ok(ConnectionState::ReadingPostBody) => {}
-                _ => {
-                    let _ = self.write_conn_to_history(conn);
-                }
+                _ => self.record_uploads(conn),
             };
 
             res
@@ -1006,6 +2795,339 @@ impl HttpTui<'_> {
         }
     }
 
+    // The script a request is for, if it is for one. Paths are resolved
+    // first, so a link in the CGI directory to a script elsewhere is never
+    // run.
+    fn find_cgi_script(&self, req: &HttpRequest) -> Option<PathBuf> {
+        let cgi_dir = self.cgi_dir.as_ref()?;
+        let path = self.root_dir.join(req.path.trim_start_matches('/'));
+        let script = get_and_check_canon_path(self.root_dir, path, self.follow_symlinks).ok()??;
+        if script.starts_with(cgi_dir) && script.is_file() {
+            Some(script)
+        } else {
+            None
+        }
+    }
+
+    fn start_cgi(
+        &self,
+        req: &HttpRequest,
+        conn: &mut HttpConnection,
+        script: &Path,
+    ) -> Result<ConnectionState, io::Error> {
+        let body_length = match get_content_length(req) {
+            Some(length) if req.method == Some(HttpMethod::POST) => length,
+            None if req.method == Some(HttpMethod::POST) && has_body(req) => {
+                return self.create_oneoff_response(
+                    HttpStatus::BadRequest,
+                    conn,
+                    Some(
+                        "Requests to scripts must give the Content-Length of their body."
+                            .to_string(),
+                    ),
+                );
+            }
+            _ => 0,
+        };
+        if self.upload_size_limit > 0 && body_length > self.upload_size_limit {
+            return self.create_oneoff_response(
+                HttpStatus::PayloadTooLarge,
+                conn,
+                Some(format!(
+                    "Upload size limit of {} bytes exceeded",
+                    self.upload_size_limit
+                )),
+            );
+        }
+
+        let mut env = vec![
+            ("GATEWAY_INTERFACE".to_string(), "CGI/1.1".to_string()),
+            ("SERVER_SOFTWARE".to_string(), "hypershare".to_string()),
+            (
+                "SERVER_PROTOCOL".to_string(),
+                version_to_str(&req.version).to_string(),
+            ),
+            (
+                "REQUEST_METHOD".to_string(),
+                method_to_str(req.method.as_ref().unwrap_or(&HttpMethod::GET)).to_string(),
+            ),
+            ("SCRIPT_NAME".to_string(), req.path.clone()),
+            (
+                "SCRIPT_FILENAME".to_string(),
+                script.to_string_lossy().to_string(),
+            ),
+            ("PATH_INFO".to_string(), String::new()),
+            (
+                "QUERY_STRING".to_string(),
+                req.query.clone().unwrap_or_default(),
+            ),
+        ];
+        if let Ok(addr) = conn.peer_addr() {
+            env.push(("REMOTE_ADDR".to_string(), addr.ip().to_string()));
+            env.push(("REMOTE_PORT".to_string(), addr.port().to_string()));
+        }
+        if let Ok(addr) = self.listener.local_addr() {
+            env.push(("SERVER_PORT".to_string(), addr.port().to_string()));
+        }
+        if body_length > 0 {
+            env.push(("CONTENT_LENGTH".to_string(), body_length.to_string()));
+        }
+        if let Some(content_type) = req.get_header("content-type") {
+            env.push(("CONTENT_TYPE".to_string(), content_type.clone()));
+        }
+        for (name, value) in req.get_headers() {
+            match name {
+                // Given above, or (for Proxy) able to redirect the script's
+                // own outgoing requests through HTTP_PROXY.
+                "content-length" | "content-type" | "authorization" | "proxy" => {}
+                _ => env.push((cgi::header_variable(name), value.to_string())),
+            }
+        }
+
+        let mut cgi = match CgiProcess::spawn(script, env, body_length) {
+            Ok(cgi) => cgi,
+            Err(e) => {
+                return self.create_oneoff_response(
+                    HttpStatus::ServerError,
+                    conn,
+                    Some(format!("Could not run the script: {}", e)),
+                );
+            }
+        };
+        // Some of the body may have arrived with the headers
+        cgi.send_body(conn.initial_body());
+        let state = if cgi.body_remaining > 0 {
+            ConnectionState::ReadingCgiBody
+        } else {
+            ConnectionState::RunningCgi
+        };
+        conn.cgi = Some(cgi);
+        Ok(state)
+    }
+
+    fn read_cgi_body(&self, conn: &mut HttpConnection) -> Result<ConnectionState, io::Error> {
+        let mut buf = [0; 64 * 1024];
+        let amt = match conn.stream.read(&mut buf) {
+            Ok(0) | Err(_) => {
+                return Ok(ConnectionState::Closing);
+            }
+            Ok(amt) => amt,
+        };
+        conn.bytes_read += amt;
+        match &mut conn.cgi {
+            Some(cgi) => {
+                cgi.send_body(&buf[..amt]);
+                if cgi.body_remaining > 0 {
+                    Ok(ConnectionState::ReadingCgiBody)
+                } else {
+                    Ok(ConnectionState::RunningCgi)
+                }
+            }
+            None => Ok(ConnectionState::Closing),
+        }
+    }
+
+    fn read_cgi_output(&self, conn: &mut HttpConnection) -> Result<ConnectionState, io::Error> {
+        let output = match &mut conn.cgi {
+            Some(cgi) => match cgi.read_output() {
+                Ok(false) => {
+                    return Ok(ConnectionState::RunningCgi);
+                }
+                Ok(true) => Ok(cgi.take_output()),
+                Err(e) => Err(e),
+            },
+            None => {
+                return Ok(ConnectionState::Closing);
+            }
+        };
+        // Done with the script either way
+        conn.cgi = None;
+
+        let parsed = match output {
+            Ok(output) => cgi::parse_output(&output),
+            Err(e) => Err(format!("The script failed: {}", e)),
+        };
+        let state = match parsed {
+            Ok(parsed) => {
+                let mut resp = HttpResponse::new(parsed.status, &HttpVersion::Http1_1);
+                resp.add_header("Server".to_string(), "hypershare".to_string());
+                for (name, value) in parsed.headers {
+                    // The body is framed by us, not the script
+                    let lower = name.to_lowercase();
+                    if lower == "content-length"
+                        || lower == "transfer-encoding"
+                        || lower == "connection"
+                    {
+                        continue;
+                    }
+                    resp.add_header(name, value);
+                }
+                let len = parsed.body.len();
+                resp.set_content_length(len);
+                resp.add_body(ResponseDataType::Bytes(io::Cursor::new(parsed.body)));
+
+                let is_head = conn.last_requested_method == Some(HttpMethod::HEAD);
+                // A body the status does not allow is not sent either
+                let sends_body = !is_head && !status_forbids_body(&resp.get_status());
+                self.send_response_headers(conn, &mut resp, is_head)?;
+                conn.response = Some(resp);
+                if sends_body {
+                    conn.bytes_requested += len;
+                }
+                ConnectionState::WritingResponse
+            }
+            Err(msg) => self.create_oneoff_response(HttpStatus::ServerError, conn, Some(msg))?,
+        };
+        Ok(state)
+    }
+
+    fn start_proxy(
+        &self,
+        req: &HttpRequest,
+        conn: &mut HttpConnection,
+        route: &ProxyRoute,
+        upstream_path: &str,
+    ) -> Result<ConnectionState, io::Error> {
+        let body_length = match get_content_length(req) {
+            Some(length) => length,
+            None if has_body(req) => {
+                return self.create_oneoff_response(
+                    HttpStatus::BadRequest,
+                    conn,
+                    Some(
+                        "Proxied requests must give the Content-Length of their body.".to_string(),
+                    ),
+                );
+            }
+            None => 0,
+        };
+        // The response is only framed by the upstream closing the connection
+        conn.keep_alive = false;
+
+        let head = route.request_head(req, upstream_path, conn.peer_addr().ok());
+        let mut proxy = match ProxyConnection::connect(
+            route,
+            head,
+            body_length,
+            self.proxy_timeout,
+            self.common_headers(),
+        ) {
+            Ok(proxy) => proxy,
+            Err(e) => {
+                return self.create_oneoff_response(
+                    proxy::upstream_error_status(&e),
+                    conn,
+                    Some("The upstream server could not be reached.".to_string()),
+                );
+            }
+        };
+        // Some of the body may have arrived with the headers
+        proxy.add_body(conn.initial_body());
+        if proxy.body_remaining > 0
+            && req.version == HttpVersion::Http1_1
+            && req.get_header("expect").is_some()
+        {
+            self.write_continue(conn)?;
+        }
+        conn.proxy = Some(proxy);
+        Ok(ConnectionState::ProxyingRequest)
+    }
+
+    fn proxy_request(&self, conn: &mut HttpConnection) -> Result<ConnectionState, io::Error> {
+        let proxy = match &mut conn.proxy {
+            Some(proxy) => proxy,
+            None => {
+                return Ok(ConnectionState::Closing);
+            }
+        };
+        if proxy.has_request_data() {
+            if let Err(e) = proxy.write_upstream() {
+                return self.proxy_failed(
+                    conn,
+                    proxy::upstream_error_status(&e),
+                    "The upstream server could not be reached.",
+                );
+            }
+        } else {
+            let mut buf = [0; 64 * 1024];
+            let len = buf.len().min(proxy.body_remaining);
+            match conn.stream.read(&mut buf[..len]) {
+                Ok(0) | Err(_) => {
+                    return Ok(ConnectionState::Closing);
+                }
+                Ok(amt) => {
+                    conn.bytes_read += amt;
+                    proxy.add_body(&buf[..amt]);
+                }
+            }
+        }
+        if proxy.has_request_data() || proxy.body_remaining > 0 {
+            Ok(ConnectionState::ProxyingRequest)
+        } else {
+            Ok(ConnectionState::ProxyingResponse)
+        }
+    }
+
+    fn proxy_response(&self, conn: &mut HttpConnection) -> Result<ConnectionState, io::Error> {
+        let proxy = match &mut conn.proxy {
+            Some(proxy) => proxy,
+            None => {
+                return Ok(ConnectionState::Closing);
+            }
+        };
+        if proxy.has_response_data() {
+            let amt = proxy.write_client(&mut conn.stream, BUFFER_SIZE)?;
+            conn.bytes_sent += amt;
+            if let Some(stats) = &self.stats {
+                stats.borrow_mut().add_bytes(amt);
+            }
+        } else {
+            if let Err((status, msg)) = proxy.read_upstream() {
+                return self.proxy_failed(conn, status, &msg);
+            }
+        }
+        match &conn.proxy {
+            Some(proxy) if proxy.is_finished() => Ok(ConnectionState::Closing),
+            _ => Ok(ConnectionState::ProxyingResponse),
+        }
+    }
+
+    // Answer with a 502 or 504, if nothing of the upstream's response has
+    // been sent. The connection closes once the error is written.
+    fn proxy_failed(
+        &self,
+        conn: &mut HttpConnection,
+        status: HttpStatus,
+        msg: &str,
+    ) -> Result<ConnectionState, io::Error> {
+        conn.proxy = None;
+        conn.keep_alive = false;
+        self.create_oneoff_response(status, conn, Some(msg.to_string()))
+    }
+
+    fn proxy_timed_out(&self, conn: &mut HttpConnection) -> ConnectionState {
+        let res = self.proxy_failed(
+            conn,
+            HttpStatus::GatewayTimeout,
+            "The upstream server took too long to respond.",
+        );
+        match res {
+            // The error is written once the socket is writable
+            Ok(state) => state,
+            Err(_) => ConnectionState::Closing,
+        }
+    }
+
+    fn read_event_stream(&self, conn: &mut HttpConnection) -> Result<ConnectionState, io::Error> {
+        // Event stream clients have nothing more to say, so this is only
+        // used to notice when they go away.
+        let mut buf = [0; 256];
+        match conn.stream.read(&mut buf) {
+            Ok(0) | Err(_) => Ok(ConnectionState::Closing),
+            Ok(_) => Ok(ConnectionState::StreamingEvents),
+        }
+    }
+
     fn handle_conn(&self, conn: &mut HttpConnection) -> Result<(), io::Error> {
         match conn.state {
             ConnectionState::ReadingRequest => {
@@ -1017,21 +3139,89 @@ impl HttpTui<'_> {
             ConnectionState::WritingResponse => {
                 conn.state = self.write_partial_final_response(conn)?;
             }
+            ConnectionState::StreamingEvents => {
+                conn.state = self.read_event_stream(conn)?;
+            }
+            ConnectionState::ReadingCgiBody => {
+                conn.state = self.read_cgi_body(conn)?;
+            }
+            ConnectionState::RunningCgi => {
+                conn.state = self.read_cgi_output(conn)?;
+            }
+            ConnectionState::ProxyingRequest => {
+                conn.state = self.proxy_request(conn)?;
+            }
+            ConnectionState::ProxyingResponse => {
+                conn.state = self.proxy_response(conn)?;
+            }
             ConnectionState::Closing => {}
         }
 
         Ok(())
     }
 
+    // Headers that go on every response, added just before it is sent
+    fn common_headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if self.nosniff {
+            headers.push(("X-Content-Type-Options".to_string(), "nosniff".to_string()));
+        }
+        headers.extend(self.extra_headers.iter().cloned());
+        headers
+    }
+
+    fn add_common_headers(&self, resp: &mut HttpResponse) {
+        for (key, value) in self.common_headers() {
+            resp.add_header(key, value);
+        }
+    }
+
     fn create_oneoff_response(
         &self,
         status: HttpStatus,
-        mut conn: &mut HttpConnection,
+        conn: &mut HttpConnection,
+        msg: Option<String>,
+    ) -> Result<ConnectionState, io::Error> {
+        self.create_oneoff_response_with_headers(status, conn, msg, vec![])
+    }
+
+    fn create_oneoff_response_with_headers(
+        &self,
+        status: HttpStatus,
+        conn: &mut HttpConnection,
         msg: Option<String>,
+        headers: Vec<(String, String)>,
+    ) -> Result<ConnectionState, io::Error> {
+        let (body, content_type) = if conn.json_errors {
+            (
+                rendering::render_error_json(&status, msg),
+                "application/json",
+            )
+        } else {
+            (
+                rendering::render_error(&status, msg),
+                "text/html; charset=utf-8",
+            )
+        };
+        self.create_response(status, conn, body, content_type, headers)
+    }
+
+    fn create_response(
+        &self,
+        status: HttpStatus,
+        conn: &mut HttpConnection,
+        body: String,
+        content_type: &str,
+        headers: Vec<(String, String)>,
     ) -> Result<ConnectionState, io::Error> {
-        let body: String = rendering::render_error(&status, msg);
+        if status_closes_connection(&status) {
+            conn.keep_alive = false;
+        }
         let mut resp = HttpResponse::new(status, &HttpVersion::Http1_1);
         resp.add_header("Server".to_string(), "hypershare".to_string());
+        for (key, value) in headers {
+            resp.add_header(key, value);
+        }
 
         resp.set_content_length(body.len());
         resp.add_header(
@@ -1042,19 +3232,24 @@ impl HttpTui<'_> {
                 "close".to_string()
             },
         );
-        resp.add_header(
-            "Content-Type".to_string(),
-            "text/html; charset=utf-8".to_string(),
-        );
-
-        // Add content-length to bytes requested
-        conn.bytes_requested += body.len();
+        resp.add_header("Content-Type".to_string(), content_type.to_string());
+        self.add_common_headers(&mut resp);
+
+        // Generated bodies are measured like files, so HEAD gets the same
+        // Content-Length that GET would, with no body.
+        let is_head = conn.last_requested_method == Some(HttpMethod::HEAD);
+        if !is_head {
+            conn.bytes_requested += body.len();
+        }
 
         let data = ResponseDataType::String(SeekableString::new(body));
 
         // Write headers
-        resp.write_headers_to_stream(&conn.stream)?;
+        resp.write_headers_to_stream(&mut conn.stream)?;
         resp.add_body(data);
+        if is_head {
+            resp.clear_body();
+        }
 
         assert_eq!(conn.response.is_none(), true);
         conn.response = Some(resp);
@@ -1063,6 +3258,23 @@ impl HttpTui<'_> {
     }
 }
 
+// One line for each part of an echoed form, e.g.
+// part 1: name="doc" filename="a.txt" type="text/plain" size=5
+fn render_upload_parts(parts: &[UploadPart]) -> String {
+    let mut summary = String::new();
+    for (idx, part) in parts.iter().enumerate() {
+        summary.push_str(&format!("part {}: name={:?}", idx + 1, part.name));
+        if let Some(filename) = &part.filename {
+            summary.push_str(&format!(" filename={:?}", filename));
+        }
+        if let Some(content_type) = &part.content_type {
+            summary.push_str(&format!(" type={:?}", content_type));
+        }
+        summary.push_str(&format!(" size={}\n", part.size));
+    }
+    summary
+}
+
 fn get_post_boundary(req: &HttpRequest) -> Option<&str> {
     let ct = req.get_header("content-type")?;
     for segment in ct.split(";") {
@@ -1080,7 +3292,120 @@ fn get_post_boundary(req: &HttpRequest) -> Option<&str> {
     None
 }
 
-fn get_and_check_canon_path(root_dir: &Path, path: PathBuf) -> Result<Option<PathBuf>, io::Error> {
+fn is_multipart(req: &HttpRequest) -> bool {
+    match req.get_header("content-type") {
+        Some(ct) => ct.trim_start().to_lowercase().starts_with("multipart/"),
+        None => false,
+    }
+}
+
+fn get_content_length(req: &HttpRequest) -> Option<usize> {
+    req.get_header("content-length")?.trim().parse().ok()
+}
+
+// Whether the link at `link`, which resolves to `target`, points at a
+// directory containing itself.
+fn is_ancestor_link(link: &Path, target: &Path) -> bool {
+    match link.parent().map(fs::canonicalize) {
+        Some(Ok(parent)) => parent.starts_with(target),
+        _ => false,
+    }
+}
+
+// The methods that are served, the ones scripts may be run with, and the ones
+// passed on to proxies.
+fn methods_from_opts(opts: &Opts) -> (Vec<HttpMethod>, Vec<HttpMethod>, Vec<HttpMethod>) {
+    // Methods are checked with the other options before this is called, so
+    // this cannot fail.
+    let methods = opts::parse_methods(&opts.methods).unwrap_or_default();
+    let mut allowed_methods = methods.clone();
+    if !opts.uploading_enabled {
+        allowed_methods.retain(|method| *method != HttpMethod::POST);
+    }
+    let mut cgi_methods = methods.clone();
+    cgi_methods.retain(|method| {
+        matches!(
+            method,
+            HttpMethod::GET | HttpMethod::HEAD | HttpMethod::POST
+        )
+    });
+    (allowed_methods, cgi_methods, methods)
+}
+
+// Compares slices of the same length in time that does not depend on where
+// they differ, so that secrets cannot be guessed a byte at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn allow_header(methods: &[HttpMethod]) -> String {
+    methods
+        .iter()
+        .map(method_to_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Whether the request is followed by a body, which has to be read before the
+// next request on the connection.
+fn has_body(req: &HttpRequest) -> bool {
+    get_content_length(req).unwrap_or(0) > 0
+        || !matches!(get_transfer_coding(req), Ok(TransferCoding::None))
+}
+
+// Tags change whenever a file is modified or changes size.
+fn generate_etag(metadata: &fs::Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "\"{:x}-{:x}-{:x}\"",
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    ))
+}
+
+// Whether an If-Match header (a list of tags, or "*") matches `etag`. Weak
+// tags never match, as If-Match needs a strong comparison.
+fn etag_matches(if_match: &str, etag: &str) -> bool {
+    if_match
+        .split(",")
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+// Whether any part of `path` below `root_dir` is a symbolic link
+fn passes_through_symlink(root_dir: &Path, path: &Path) -> bool {
+    let relative = match path.strip_prefix(root_dir) {
+        Ok(relative) => relative,
+        Err(_) => {
+            return true;
+        }
+    };
+    let mut current = root_dir.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        if let Ok(meta) = fs::symlink_metadata(&current) {
+            if meta.file_type().is_symlink() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn get_and_check_canon_path(
+    root_dir: &Path,
+    path: PathBuf,
+    follow_symlinks: SymlinkMode,
+) -> Result<Option<PathBuf>, io::Error> {
+    if follow_symlinks == SymlinkMode::Off && passes_through_symlink(root_dir, &path) {
+        return Ok(None);
+    }
+    // Links are trusted, but the path itself must not climb out of the root
+    if follow_symlinks == SymlinkMode::All && path.components().any(|c| c == Component::ParentDir) {
+        return Ok(None);
+    }
+
     let canonical_path = match fs::canonicalize(path) {
         Err(error) => {
             return Err(error);
@@ -1088,7 +3413,7 @@ fn get_and_check_canon_path(root_dir: &Path, path: PathBuf) -> Result<Option<Pat
         Ok(path) => path,
     };
 
-    if !canonical_path.starts_with(root_dir) {
+    if follow_symlinks != SymlinkMode::All && !canonical_path.starts_with(root_dir) {
         // Use 404 so that the user cannot determine if directories
         // exist or not.
         return Ok(None);
@@ -1,10 +1,14 @@
 mod types;
 
 use types::PostBufferError;
+pub use types::UploadPart;
 
 use crate::http::http_core::HttpStatus;
 
-use std::fs::{self, OpenOptions};
+use std::{
+    cmp::min,
+    fs::{self, OpenOptions},
+};
 
 use std::io::{self, Write};
 
@@ -13,23 +17,147 @@ use std::path::PathBuf;
 use core::ptr::copy;
 
 use boyer_moore_magiclen::BMByte;
+use flate2::write::GzDecoder;
+use nix::errno::Errno;
 
 use crate::http::boyer_moore::{find_body_start, types::BMBuf};
 
 const POST_BUFFER_SIZE: usize = 32 * 1024 * 1024;
+// Longest chunk size or trailer line accepted in a chunked body
+const MAX_CHUNK_LINE: usize = 4096;
+// Most of a chunked body read at once, before it is decoded into the buffer
+const CHUNKED_READ_SIZE: usize = 64 * 1024;
 
 #[derive(PartialEq)]
 enum PostRequestState {
     AwaitingFirstBody,
     AwaitingBody,
     AwaitingMeta,
+    AwaitingRawBody,
     DiscardingData,
 }
 
+// The file a compressed upload is decompressed into. Writes past `limit` (0
+// for none) fail, so that a small body cannot expand into a huge file.
+struct LimitedFile {
+    file: fs::File,
+    written: usize,
+    limit: usize,
+    exceeded: bool,
+}
+
+impl Write for LimitedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.limit > 0 && self.written + buf.len() > self.limit {
+            self.exceeded = true;
+            return Err(io::Error::other("upload size limit exceeded"));
+        }
+        let written = self.file.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.file.flush() }
+}
+
+enum ChunkState {
+    Size,
+    // Bytes of the current chunk that have yet to be received
+    Data(usize),
+    // The CRLF after a chunk's data
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+// Undoes `Transfer-Encoding: chunked` as the body arrives. Chunk sizes can be
+// split across reads, so partial lines are kept in `line`.
+struct ChunkedDecoder {
+    state: ChunkState,
+    line: Vec<u8>,
+}
+
+fn malformed_chunks() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed chunked body")
+}
+
+impl ChunkedDecoder {
+    fn new() -> ChunkedDecoder {
+        ChunkedDecoder {
+            state: ChunkState::Size,
+            line: Vec::new(),
+        }
+    }
+
+    fn is_done(&self) -> bool { matches!(self.state, ChunkState::Done) }
+
+    // Decode `input` into `output`, returning how much was written there.
+    // That is never more than the length of `input`. Anything after the last
+    // chunk's trailers is ignored.
+    fn decode(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        let mut idx = 0;
+        while idx < input.len() {
+            match self.state {
+                ChunkState::Done => break,
+                ChunkState::Data(remaining) => {
+                    let take = min(remaining, input.len() - idx);
+                    output[written..written + take].copy_from_slice(&input[idx..idx + take]);
+                    written += take;
+                    idx += take;
+                    self.state = if take == remaining {
+                        ChunkState::DataEnd
+                    } else {
+                        ChunkState::Data(remaining - take)
+                    };
+                }
+                _ => {
+                    let byte = input[idx];
+                    idx += 1;
+                    if byte != b'\n' {
+                        if self.line.len() >= MAX_CHUNK_LINE {
+                            return Err(malformed_chunks());
+                        }
+                        self.line.push(byte);
+                        continue;
+                    }
+                    let line = std::mem::take(&mut self.line);
+                    let line = line.strip_suffix(b"\r").unwrap_or(&line);
+                    self.state = match self.state {
+                        ChunkState::Size => match parse_chunk_size(line) {
+                            Some(0) => ChunkState::Trailers,
+                            Some(size) => ChunkState::Data(size),
+                            None => return Err(malformed_chunks()),
+                        },
+                        ChunkState::DataEnd if line.is_empty() => ChunkState::Size,
+                        ChunkState::DataEnd => return Err(malformed_chunks()),
+                        // Trailers are not used for anything
+                        _ if line.is_empty() => ChunkState::Done,
+                        _ => ChunkState::Trailers,
+                    };
+                }
+            }
+        }
+        Ok(written)
+    }
+}
+
+// The hex size at the start of a chunk, before any extensions
+fn parse_chunk_size(line: &[u8]) -> Option<usize> {
+    let digits = line.split(|b| *b == b';').next()?;
+    let digits = std::str::from_utf8(digits)
+        .ok()?
+        .trim_matches(|c| c == ' ' || c == '\t');
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    usize::from_str_radix(digits, 16).ok()
+}
+
 pub struct PostBuffer {
     fill_location: usize,
     buffer: Box<[u8]>,
-    post_delimeter: BMByte,
+    post_delimeter: Option<BMByte>,
     post_delimeter_string: String,
     current_filename: Option<PathBuf>,
     current_file: Option<fs::File>,
@@ -38,8 +166,61 @@ pub struct PostBuffer {
     parse_idx: usize,
     queued_error: PostBufferError,
     new_files: Vec<String>,
+    // Parts without a filename are form fields, and are kept in memory
+    current_field: Option<(String, Vec<u8>)>,
+    fields: Vec<(String, String)>,
     total_written: usize,
     size_limit: usize,
+    // Bytes of a raw (non-multipart) body that have yet to be received
+    raw_remaining: Option<usize>,
+    // Bytes read since an error, and how many to read before giving up on
+    // delivering the error politely (0 for no limit)
+    discarded: usize,
+    discard_limit: usize,
+    // Appended to the name of every stored file, e.g. "upload"
+    forced_extension: Option<String>,
+    // Whether a '+' in a multipart filename stands for a space
+    plus_as_space: bool,
+    // Whether a raw body is gzip-compressed, and is stored decompressed
+    gzip: bool,
+    // Takes the place of `current_file` for a compressed body
+    decoder: Option<GzDecoder<LimitedFile>>,
+    // Whether to parse the form without storing anything, recording each
+    // part in `parts` instead
+    dry_run: bool,
+    parts: Vec<UploadPart>,
+    // Set for a body sent with `Transfer-Encoding: chunked`. Only decoded
+    // data is put in `buffer`.
+    dechunker: Option<ChunkedDecoder>,
+}
+
+// Explain why a new file could not be created, so that a name that is taken
+// is not confused with a directory the server cannot write to.
+pub fn open_error(error: &io::Error, filename: &str) -> PostBufferError {
+    match error.kind() {
+        io::ErrorKind::AlreadyExists => PostBufferError::new(
+            HttpStatus::Conflict,
+            format!(
+                "A file named {} already exists. Please use a different name.",
+                filename
+            ),
+        ),
+        io::ErrorKind::PermissionDenied => PostBufferError::new(
+            HttpStatus::PermissionDenied,
+            "Uploads are not allowed in this directory.".to_string(),
+        ),
+        io::ErrorKind::NotFound => PostBufferError::new(
+            HttpStatus::NotFound,
+            "The directory to upload to does not exist.".to_string(),
+        ),
+        _ if error.raw_os_error() == Some(Errno::EROFS as i32) => PostBufferError::server_error(
+            "The upload directory is on a read-only filesystem.".to_string(),
+        ),
+        _ if error.raw_os_error() == Some(Errno::ENOSPC as i32) => {
+            PostBufferError::server_error("The server is out of disk space.".to_string())
+        }
+        _ => PostBufferError::server_error(format!("Could not create {}: {}", filename, error)),
+    }
 }
 
 impl PostBuffer {
@@ -49,6 +230,44 @@ impl PostBuffer {
         delim_str: String,
         slice: &[u8],
         size_limit: usize,
+        discard_limit: usize,
+        forced_extension: Option<String>,
+    ) -> PostBuffer {
+        let mut pb =
+            PostBuffer::with_initial_data(dir, slice, size_limit, discard_limit, forced_extension);
+        pb.post_delimeter = Some(delim);
+        pb.post_delimeter_string = delim_str;
+        pb.total_written += pb.fill_location;
+
+        pb
+    }
+
+    // Create a buffer for a body that is the file itself, rather than a
+    // multipart form. `length` comes from the request's Content-Length.
+    pub fn new_raw(
+        dir: PathBuf,
+        filename: String,
+        length: usize,
+        slice: &[u8],
+        size_limit: usize,
+        discard_limit: usize,
+        forced_extension: Option<String>,
+    ) -> PostBuffer {
+        let mut pb =
+            PostBuffer::with_initial_data(dir, slice, size_limit, discard_limit, forced_extension);
+        pb.state = PostRequestState::AwaitingRawBody;
+        pb.new_files.push(filename);
+        pb.raw_remaining = Some(length);
+
+        pb
+    }
+
+    fn with_initial_data(
+        dir: PathBuf,
+        slice: &[u8],
+        size_limit: usize,
+        discard_limit: usize,
+        forced_extension: Option<String>,
     ) -> PostBuffer {
         let mut pb = PostBuffer {
             buffer: {
@@ -59,8 +278,8 @@ impl PostBuffer {
                 v.into_boxed_slice()
             },
             fill_location: slice.len(),
-            post_delimeter: delim,
-            post_delimeter_string: delim_str,
+            post_delimeter: None,
+            post_delimeter_string: String::new(),
             current_filename: None,
             current_file: None,
             state: PostRequestState::AwaitingFirstBody,
@@ -68,29 +287,102 @@ impl PostBuffer {
             parse_idx: 0,
             queued_error: PostBufferError::no_error(),
             new_files: Vec::<String>::new(),
+            current_field: None,
+            fields: Vec::new(),
             total_written: 0,
             size_limit: size_limit,
+            raw_remaining: None,
+            discarded: 0,
+            discard_limit: discard_limit,
+            forced_extension: forced_extension,
+            plus_as_space: false,
+            gzip: false,
+            decoder: None,
+            dry_run: false,
+            parts: Vec::new(),
+            dechunker: None,
         };
         pb.buffer[..pb.fill_location].clone_from_slice(slice);
-        pb.total_written += pb.fill_location;
 
         pb
     }
 
+    pub fn set_plus_as_space(&mut self, plus_as_space: bool) { self.plus_as_space = plus_as_space; }
+
+    // Store a raw body decompressed. The size limit then applies to the
+    // decompressed size.
+    pub fn set_gzip(&mut self, gzip: bool) { self.gzip = gzip; }
+
+    // Dechunk the body as it arrives. A raw body then ends with its last
+    // chunk rather than after a given length.
+    pub fn set_chunked(&mut self) -> io::Result<()> {
+        let received = self.buffer[..self.fill_location].to_vec();
+        let mut decoder = ChunkedDecoder::new();
+        self.fill_location = decoder.decode(&received, &mut self.buffer)?;
+        self.dechunker = Some(decoder);
+        self.check_last_chunk();
+        Ok(())
+    }
+
+    // Once the last chunk is in, whatever is buffered is the rest of a raw
+    // body
+    fn check_last_chunk(&mut self) {
+        let done = self.dechunker.as_ref().is_some_and(|d| d.is_done());
+        if done && self.raw_remaining.is_some() {
+            self.raw_remaining = Some(self.fill_location - self.parse_idx);
+        }
+    }
+
+    // Whether any of the body is waiting to be handled
+    pub fn has_buffered_data(&self) -> bool { self.fill_location > 0 }
+
+    // Parse a form without storing any of it. Files are read and thrown
+    // away, and each part is recorded for `get_parts`.
+    pub fn set_dry_run(&mut self, dry_run: bool) { self.dry_run = dry_run; }
+
+    pub fn is_dry_run(&self) -> bool { self.dry_run }
+
+    // The parts seen in a dry run
+    pub fn get_parts(&self) -> &Vec<UploadPart> { &self.parts }
+
     pub fn get_new_files(&self) -> &Vec<String> { &self.new_files }
 
+    // Names and values of the non-file fields received so far
+    pub fn get_fields(&self) -> &Vec<(String, String)> { &self.fields }
+
     pub fn read_into_buffer<T>(&mut self, readable: &mut T) -> Result<usize, io::Error>
     where
         T: io::Read,
     {
-        let read = readable.read(&mut self.buffer[self.fill_location..])?;
-        self.fill_location += read;
+        let read = match &mut self.dechunker {
+            Some(decoder) => {
+                let space = self.buffer.len() - self.fill_location;
+                let mut received = vec![0; min(space, CHUNKED_READ_SIZE)];
+                let read = readable.read(&mut received)?;
+                let was_done = decoder.is_done();
+                self.fill_location +=
+                    decoder.decode(&received[..read], &mut self.buffer[self.fill_location..])?;
+                if !was_done {
+                    self.check_last_chunk();
+                }
+                read
+            }
+            None => {
+                let read = readable.read(&mut self.buffer[self.fill_location..])?;
+                self.fill_location += read;
+                read
+            }
+        };
+        if self.state == PostRequestState::DiscardingData {
+            self.discarded += read;
+        }
         Ok(read)
     }
 
     fn find_next_delim(&self, start: usize) -> Option<usize> {
         let vec = self
             .post_delimeter
+            .as_ref()?
             .find_in(BMBuf(&self.buffer[start..self.fill_location]), 1);
         if vec.len() < 1 {
             None
@@ -100,7 +392,7 @@ impl PostBuffer {
     }
 
     fn write_to_file_final(&mut self, limit: usize) -> Result<(), PostBufferError> {
-        if self.current_file.is_none() {
+        if self.current_file.is_none() && self.current_field.is_none() && !self.dry_run {
             return Err(PostBufferError::server_error(
                 "Attempted to write to a file before opening it.".to_string(),
             ));
@@ -115,6 +407,10 @@ impl PostBuffer {
         self.write_and_shuffle(limit)?;
 
         self.current_file = None;
+        if let Some((name, value)) = self.current_field.take() {
+            self.fields
+                .push((name, String::from_utf8_lossy(&value).to_string()));
+        }
 
         Ok(())
     }
@@ -143,45 +439,130 @@ impl PostBuffer {
         self.fill_location = remain;
     }
 
-    fn write_and_shuffle(&mut self, up_to: usize) -> Result<(), PostBufferError> {
+    fn write_and_shuffle(&mut self, up_to: usize) -> Result<usize, PostBufferError> {
         if up_to <= self.parse_idx {
             // Need to read more before this can occur
-            return Ok(());
+            return Ok(0);
         }
 
-        if self.size_limit > 0 && self.total_written + up_to - self.parse_idx > self.size_limit {
+        if self.decoder.is_none()
+            && self.size_limit > 0
+            && self.total_written + up_to - self.parse_idx > self.size_limit
+        {
             return Err(PostBufferError::new(
                 HttpStatus::PayloadTooLarge,
                 format!("Upload size limit of {} bytes exceeded", self.size_limit),
             ));
         }
 
-        let written = match self
-            .current_file
-            .as_ref()
-            .unwrap()
-            .write(&self.buffer[self.parse_idx..up_to])
-        {
-            Ok(size) => size,
-            Err(_) => {
-                return Err(PostBufferError::server_error(
-                    "Error writing to file.".to_string(),
-                ));
+        let written = if let Some((_, ref mut value)) = self.current_field {
+            value.extend_from_slice(&self.buffer[self.parse_idx..up_to]);
+            up_to - self.parse_idx
+        } else if let Some(decoder) = &mut self.decoder {
+            match decoder.write(&self.buffer[self.parse_idx..up_to]) {
+                Ok(size) => size,
+                Err(_) => {
+                    return Err(self.decode_error());
+                }
+            }
+        } else if self.dry_run {
+            up_to - self.parse_idx
+        } else {
+            match self
+                .current_file
+                .as_ref()
+                .unwrap()
+                .write(&self.buffer[self.parse_idx..up_to])
+            {
+                Ok(size) => size,
+                Err(_) => {
+                    return Err(PostBufferError::server_error(
+                        "Error writing to file.".to_string(),
+                    ));
+                }
             }
         };
 
         self.parse_idx += written;
         self.total_written += written;
+        if self.dry_run {
+            if let Some(part) = self.parts.last_mut() {
+                part.size += written;
+            }
+        }
 
         let amount_remaining: usize = self.fill_location - self.parse_idx;
 
         self.shuffle(amount_remaining);
 
+        Ok(written)
+    }
+
+    // Decompressing fails either on a body that is not gzip, or on one that
+    // expands past the size limit.
+    fn decode_error(&self) -> PostBufferError {
+        let over_limit = self
+            .decoder
+            .as_ref()
+            .is_some_and(|decoder| decoder.get_ref().exceeded);
+        if over_limit {
+            PostBufferError::new(
+                HttpStatus::PayloadTooLarge,
+                format!("Upload size limit of {} bytes exceeded", self.size_limit),
+            )
+        } else {
+            PostBufferError::new(
+                HttpStatus::BadRequest,
+                "The upload is not valid gzip.".to_string(),
+            )
+        }
+    }
+
+    // The name a file is stored under. Names already ending in the forced
+    // extension are kept, so that re-uploading a downloaded file works.
+    fn stored_filename(&self, filename: &str) -> String {
+        match &self.forced_extension {
+            Some(ext) if !filename.ends_with(&format!(".{}", ext)) => {
+                format!("{}.{}", filename, ext)
+            }
+            _ => filename.to_string(),
+        }
+    }
+
+    fn open_new_file(&mut self, filename: &str) -> Result<(), PostBufferError> {
+        // `create_new` below also keeps a rewritten name from replacing an
+        // existing file.
+        let real_filename = self.dir.join(self.stored_filename(filename));
+
+        self.current_file = Some(
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&real_filename)
+            {
+                Ok(f) => f,
+                Err(_) if real_filename.is_dir() => {
+                    return Err(PostBufferError::new(
+                        HttpStatus::Conflict,
+                        format!(
+                            "A directory named {} already exists. Please use a different name.",
+                            filename
+                        ),
+                    ));
+                }
+                Err(e) => {
+                    return Err(open_error(&e, filename));
+                }
+            },
+        );
+
+        self.current_filename = Some(real_filename);
+
         Ok(())
     }
 
     fn send_buffer_data_to_file(&mut self, limit: usize) -> Result<(), PostBufferError> {
-        if self.current_file.is_none() {
+        if self.current_file.is_none() && self.current_field.is_none() && !self.dry_run {
             return Err(PostBufferError::server_error(
                 "Attempted to write to a file before opening it.".to_string(),
             ));
@@ -213,13 +594,20 @@ impl PostBuffer {
      */
     /* If it is desirable to simply have bad POST requests get a TCP RST
      * with no error message (although one is sent before the reset, browsers
-     * won't display it), call `handle_new_data()` directly.
+     * won't display it), call `handle_new_data()` directly. This is what
+     * --fast-reject-uploads does.
+     */
+    /* Discarding is bounded by --discard-limit. Otherwise a client could keep
+     * a connection busy forever by never sending the final delimeter. Past the
+     * limit, the error is returned, and the connection is closed with the
+     * rest of the body unread.
      */
     pub fn handle_new_data_queue_error(&mut self) -> Result<bool, PostBufferError> {
         loop {
             match self.handle_new_data() {
                 Ok(done) => {
-                    if done && self.state == PostRequestState::DiscardingData {
+                    let over_limit = self.discard_limit > 0 && self.discarded > self.discard_limit;
+                    if (done || over_limit) && self.state == PostRequestState::DiscardingData {
                         return Err(self.queued_error.clone());
                     } else {
                         return Ok(done);
@@ -240,12 +628,8 @@ impl PostBuffer {
         match res {
             Ok(_) => {}
             Err(ref mut e) => {
-                if let Some(ref s) = self.current_filename {
-                    if let Err(io_e) = fs::remove_file(s) {
-                        e.add_error(&PostBufferError::server_error(format!("{:?}", io_e)));
-                    }
-                    self.current_filename = None;
-                    self.current_file = None; // close if open
+                if let Err(io_e) = self.remove_current_file() {
+                    e.add_error(&PostBufferError::server_error(format!("{:?}", io_e)));
                 }
             }
         };
@@ -253,10 +637,30 @@ impl PostBuffer {
         res
     }
 
+    // Delete the file being written, for a body that will never be finished
+    pub fn remove_current_file(&mut self) -> io::Result<()> {
+        if let Some(s) = self.current_filename.take() {
+            self.current_file = None; // close if open
+            self.decoder = None;
+            fs::remove_file(s)?;
+        }
+        Ok(())
+    }
+
     pub fn handle_new_data_raw(&mut self) -> Result<bool, PostBufferError> {
         // Where parsing should begin
         loop {
             match self.state {
+                PostRequestState::DiscardingData if self.raw_remaining.is_some() => {
+                    // There is no delimeter to look for, so just drop bytes until
+                    // the declared length has been received.
+                    let remaining = self.raw_remaining.unwrap();
+                    let discard = min(self.fill_location - self.parse_idx, remaining);
+                    self.parse_idx += discard;
+                    self.raw_remaining = Some(remaining - discard);
+                    self.shuffle(self.fill_location - self.parse_idx);
+                    return Ok(remaining == discard);
+                }
                 PostRequestState::DiscardingData => {
                     let new_idx = match self.find_next_delim(self.parse_idx) {
                         None => {
@@ -307,6 +711,48 @@ impl PostBuffer {
 
                     self.state = PostRequestState::AwaitingMeta;
                 }
+                PostRequestState::AwaitingRawBody => {
+                    if self.current_file.is_none() && self.decoder.is_none() {
+                        let filename = self.new_files[0].clone();
+                        self.open_new_file(&filename)?;
+                        if self.gzip {
+                            self.decoder = Some(GzDecoder::new(LimitedFile {
+                                file: self.current_file.take().unwrap(),
+                                written: 0,
+                                limit: self.size_limit,
+                                exceeded: false,
+                            }));
+                        }
+                    }
+
+                    let remaining = self.raw_remaining.unwrap_or(0);
+                    let pending = min(self.fill_location - self.parse_idx, remaining);
+                    let written = self.write_and_shuffle(self.parse_idx + pending)?;
+                    self.raw_remaining = Some(remaining - written);
+
+                    if remaining == written {
+                        if let Some(decoder) = &mut self.decoder {
+                            // Checks that the body was not cut short
+                            if decoder.try_finish().is_err() {
+                                return Err(self.decode_error());
+                            }
+                        }
+                        self.current_file = None;
+                        self.decoder = None;
+                        return Ok(true);
+                    }
+
+                    if self.decoder.is_some() && written == 0 && pending > 0 {
+                        // The decoder takes nothing after the end of the
+                        // compressed data
+                        return Err(self.decode_error());
+                    }
+
+                    if written == 0 || self.fill_location == 0 {
+                        // Wait for more of the body to arrive
+                        return Ok(false);
+                    }
+                }
                 PostRequestState::AwaitingBody => {
                     let end = match self.find_next_delim(self.parse_idx) {
                         None => {
@@ -342,6 +788,7 @@ impl PostBuffer {
                     let meta_str = String::from_utf8_lossy(meta).to_string();
 
                     let mut info: &str = "";
+                    let mut content_type = None;
 
                     for line in meta_str.split("\r\n") {
                         let (head, val) = line.split_at(match line.find(":") {
@@ -350,9 +797,14 @@ impl PostBuffer {
                                 continue;
                             }
                         });
-                        if head.to_lowercase() == "content-disposition:" {
-                            info = val;
-                            break;
+                        match &head.to_lowercase()[..] {
+                            "content-disposition:" if info.is_empty() => {
+                                info = val;
+                            }
+                            "content-type:" => {
+                                content_type = Some(val.trim().to_string());
+                            }
+                            _ => {}
                         }
                     }
                     if info == "" {
@@ -363,22 +815,49 @@ impl PostBuffer {
                     }
 
                     let mut filename: &str = "";
+                    let mut name: &str = "";
                     for kv in info.split(";") {
                         if let Some(idx) = kv.find("=") {
                             let (k, v) = kv.split_at(idx);
-                            if k.trim_start() == "filename" {
-                                // 1.. to discard '='
-                                filename = &v[1..].trim();
-                                break;
+                            // 1.. to discard '='
+                            match k.trim_start() {
+                                "filename" => {
+                                    filename = &v[1..].trim();
+                                }
+                                "name" => {
+                                    name = v[1..].trim();
+                                }
+                                _ => {}
                             }
                         }
                     }
 
                     if filename == "" {
-                        return Err(PostBufferError::new(
-                            HttpStatus::UnprocessableEntity,
-                            "Could not find attribute with a filename".to_string(),
-                        ));
+                        if name.is_empty() {
+                            return Err(PostBufferError::new(
+                                HttpStatus::UnprocessableEntity,
+                                "Could not find attribute with a filename or name".to_string(),
+                            ));
+                        }
+
+                        // A plain form field rather than a file
+                        if name.starts_with("\"") && name.len() > 1 {
+                            name = &name[1..name.len() - 1];
+                        }
+                        self.current_field = Some((name.to_string(), Vec::new()));
+                        if self.dry_run {
+                            self.parts.push(UploadPart {
+                                name: name.to_string(),
+                                filename: None,
+                                content_type,
+                                size: 0,
+                            });
+                        }
+
+                        self.state = PostRequestState::AwaitingBody;
+
+                        self.parse_idx = body_start;
+                        continue;
                     }
 
                     if filename.contains("/") {
@@ -388,32 +867,40 @@ impl PostBuffer {
                         ));
                     }
 
-                    if filename.starts_with("\"") {
+                    if filename.starts_with("\"") && filename.len() > 1 {
                         filename = &filename[1..filename.len() - 1];
                     }
 
-                    self.new_files.push(filename.to_string());
-
-                    let real_filename = self.dir.join(filename);
-
-                    self.current_file = Some(
-                        match OpenOptions::new()
-                            .write(true)
-                            .create_new(true)
-                            .open(&real_filename)
-                        {
-                            Ok(f) => f,
-                            _ => {
-                                return Err(PostBufferError::server_error(
-                                    "Could not open file for writing. If the file already exists, \
-                                     please use a different name."
-                                        .to_string(),
-                                ));
-                            }
-                        },
-                    );
+                    // `filename=""` is only empty once unquoted, and would
+                    // name the directory itself
+                    if filename.is_empty() || filename == "\"" {
+                        return Err(PostBufferError::new(
+                            HttpStatus::UnprocessableEntity,
+                            "Uploaded files must have a name.".to_string(),
+                        ));
+                    }
+
+                    let filename = if self.plus_as_space {
+                        filename.replace('+', " ")
+                    } else {
+                        filename.to_string()
+                    };
 
-                    self.current_filename = Some(real_filename);
+                    if self.dry_run {
+                        let mut name = name;
+                        if name.starts_with("\"") && name.len() > 1 {
+                            name = &name[1..name.len() - 1];
+                        }
+                        self.parts.push(UploadPart {
+                            name: name.to_string(),
+                            filename: Some(filename),
+                            content_type,
+                            size: 0,
+                        });
+                    } else {
+                        self.new_files.push(filename.clone());
+                        self.open_new_file(&filename)?;
+                    }
 
                     self.state = PostRequestState::AwaitingBody;
 
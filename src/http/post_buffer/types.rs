@@ -1,5 +1,14 @@
 use crate::http::http_core::HttpStatus;
 
+// What a dry run learned about one part of a form
+pub struct UploadPart {
+    pub name: String,
+    // Only parts that are files have one
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub size: usize,
+}
+
 #[derive(Clone)]
 pub struct PostBufferError {
     code: HttpStatus,
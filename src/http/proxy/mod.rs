@@ -0,0 +1,380 @@
+use nix::{
+    errno::Errno,
+    sys::socket::{connect, socket, AddressFamily, InetAddr, SockAddr, SockFlag, SockType},
+};
+
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use super::http_core::{method_to_str, HttpRequest, HttpStatus};
+
+// Largest response head accepted from an upstream
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+// Headers that only describe a single connection, and so are never passed on
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// Requests under `prefix` are passed on to the server at `addr`
+pub struct ProxyRoute {
+    // With a leading '/' and no trailing one, e.g. "/api"
+    prefix: String,
+    addr: SocketAddr,
+    // Sent as the Host, e.g. "127.0.0.1:8080"
+    authority: String,
+    // What `prefix` is replaced with upstream, if the URL had a path
+    path: Option<String>,
+}
+
+impl ProxyRoute {
+    // The upstream is looked up once, here, so that serving never waits on a
+    // DNS lookup.
+    pub fn new(prefix: &str, authority: &str, path: Option<&str>) -> Result<ProxyRoute, io::Error> {
+        // Port 80 unless one is given, as in "host:8080" or "[::1]:8080"
+        let lookup = match authority.rfind(':') {
+            Some(idx) if !authority[idx..].contains(']') => authority.to_string(),
+            _ => format!("{}:80", authority),
+        };
+        let addr = lookup.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no address for the upstream")
+        })?;
+        Ok(ProxyRoute {
+            prefix: format!("/{}", prefix.trim_matches('/')),
+            addr,
+            authority: authority.to_string(),
+            path: path.map(|path| path.trim_end_matches('/').to_string()),
+        })
+    }
+
+    // The path to request upstream for `path`, if it is under this route.
+    // Paths that climb with ".." are never passed on, since the upstream
+    // could resolve them to somewhere outside the route.
+    pub fn upstream_path(&self, path: &str) -> Option<String> {
+        let rest = if self.prefix == "/" {
+            path
+        } else {
+            path.strip_prefix(&self.prefix[..])?
+        };
+        // "/api" covers "/api/users", but not "/apiary"
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        if path.split('/').any(|segment| segment == "..") {
+            return None;
+        }
+        let upstream = match &self.path {
+            Some(base) => format!("{}{}", base, rest),
+            None => path.to_string(),
+        };
+        Some(if upstream.is_empty() {
+            "/".to_string()
+        } else {
+            upstream
+        })
+    }
+
+    // The request to send upstream for `req`. It is sent as HTTP/1.0, so
+    // that the response is never chunked and ends when the upstream closes
+    // the connection.
+    pub fn request_head(
+        &self,
+        req: &HttpRequest,
+        upstream_path: &str,
+        peer: Option<SocketAddr>,
+    ) -> Vec<u8> {
+        let method = req.method.as_ref().map(method_to_str).unwrap_or("GET");
+        let mut head = format!("{} {}", method, encode_path(upstream_path));
+        if let Some(query) = &req.query {
+            head.push('?');
+            head.push_str(query);
+        }
+        head.push_str(" HTTP/1.0\r\n");
+        head.push_str(&format!("Host: {}\r\n", self.authority));
+
+        let connection = req.get_header_all("connection").join(",");
+        for (name, value) in req.get_headers() {
+            match name {
+                "host" | "expect" | "x-forwarded-for" => {}
+                _ if is_end_to_end(name, Some(&connection)) => {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                _ => {}
+            }
+        }
+        if let Some(peer) = peer {
+            let mut forwarded_for = req.get_header_all("x-forwarded-for");
+            let ip = peer.ip().to_string();
+            forwarded_for.push(&ip);
+            let forwarded_for = forwarded_for.join(", ");
+            head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        head.into_bytes()
+    }
+}
+
+// A request being passed on to an upstream, and its response on its way back.
+// Both directions go through buffers, so that neither side is read from until
+// the other has taken what came before.
+pub struct ProxyConnection {
+    upstream: TcpStream,
+    // The request, then its body
+    to_upstream: Vec<u8>,
+    // Bytes of the request body that have yet to be received
+    pub body_remaining: usize,
+    // The response head, until all of it has arrived
+    head: Vec<u8>,
+    head_done: bool,
+    to_client: Vec<u8>,
+    upstream_done: bool,
+    // Status code of the response, for the history
+    pub status: Option<String>,
+    // When to give up on the upstream, if it has not sent a response head
+    deadline: Option<Instant>,
+    // Added to the response, in place of any the upstream sent by the same
+    // names
+    extra_headers: Vec<(String, String)>,
+}
+
+// How a failure to talk to the upstream is reported to the client
+pub fn upstream_error_status(error: &io::Error) -> HttpStatus {
+    match error.kind() {
+        io::ErrorKind::TimedOut => HttpStatus::GatewayTimeout,
+        _ => HttpStatus::BadGateway,
+    }
+}
+
+impl ProxyConnection {
+    // Start connecting to `route`. The connection is finished by the event
+    // loop, and `head` is sent once it is. The upstream has `timeout` (if
+    // any) to start its response.
+    pub fn connect(
+        route: &ProxyRoute,
+        head: Vec<u8>,
+        body_length: usize,
+        timeout: Option<Duration>,
+        extra_headers: Vec<(String, String)>,
+    ) -> Result<ProxyConnection, io::Error> {
+        let family = match route.addr {
+            SocketAddr::V4(_) => AddressFamily::Inet,
+            SocketAddr::V6(_) => AddressFamily::Inet6,
+        };
+        let fd = socket(family, SockType::Stream, SockFlag::SOCK_NONBLOCK, None)
+            .map_err(io::Error::other)?;
+        // Owned from here on, so that it is closed on errors
+        let upstream = unsafe { TcpStream::from_raw_fd(fd) };
+        match connect(fd, &SockAddr::new_inet(InetAddr::from_std(&route.addr))) {
+            Ok(()) | Err(nix::Error::Sys(Errno::EINPROGRESS)) => {}
+            Err(e) => {
+                return Err(io::Error::other(e));
+            }
+        }
+        Ok(ProxyConnection {
+            upstream,
+            to_upstream: head,
+            body_remaining: body_length,
+            head: Vec::new(),
+            head_done: false,
+            to_client: Vec::new(),
+            upstream_done: false,
+            status: None,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            extra_headers,
+        })
+    }
+
+    // When the upstream runs out of time, if it is still to send a head
+    pub fn get_deadline(&self) -> Option<Instant> {
+        if self.head_done {
+            None
+        } else {
+            self.deadline
+        }
+    }
+
+    pub fn get_fd(&self) -> RawFd { self.upstream.as_raw_fd() }
+
+    pub fn has_request_data(&self) -> bool { !self.to_upstream.is_empty() }
+
+    pub fn has_response_data(&self) -> bool { !self.to_client.is_empty() }
+
+    // Whether everything the upstream sent has been passed on
+    pub fn is_finished(&self) -> bool { self.upstream_done && self.to_client.is_empty() }
+
+    // Queue part of the request body. Anything past the declared length is
+    // dropped.
+    pub fn add_body(&mut self, data: &[u8]) {
+        let data = &data[..data.len().min(self.body_remaining)];
+        self.body_remaining -= data.len();
+        self.to_upstream.extend_from_slice(data);
+    }
+
+    // Send what we can of the request. Failing to connect shows up here.
+    pub fn write_upstream(&mut self) -> Result<(), io::Error> {
+        match self.upstream.write(&self.to_upstream) {
+            Ok(amt) => {
+                self.to_upstream.drain(..amt);
+                Ok(())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Read what is available of the response. Errors are only returned
+    // while the head is incomplete, since after that the client has already
+    // been sent a status.
+    pub fn read_upstream(&mut self) -> Result<(), (HttpStatus, String)> {
+        let mut buf = [0; 64 * 1024];
+        let amt = match self.upstream.read(&mut buf) {
+            Ok(amt) => amt,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Ok(());
+            }
+            Err(ref e) if !self.head_done => {
+                return Err((
+                    upstream_error_status(e),
+                    "The upstream server could not be reached.".to_string(),
+                ));
+            }
+            Err(_) => 0,
+        };
+        if amt == 0 {
+            self.upstream_done = true;
+            if !self.head_done {
+                return Err((
+                    HttpStatus::BadGateway,
+                    "The upstream server sent an incomplete response.".to_string(),
+                ));
+            }
+            return Ok(());
+        }
+        if self.head_done {
+            self.to_client.extend_from_slice(&buf[..amt]);
+            return Ok(());
+        }
+
+        self.head.extend_from_slice(&buf[..amt]);
+        let end = match self.head.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => end,
+            None if self.head.len() > MAX_HEAD_SIZE => {
+                return Err((
+                    HttpStatus::BadGateway,
+                    "The upstream server sent too many headers.".to_string(),
+                ));
+            }
+            None => {
+                return Ok(());
+            }
+        };
+        let (status, head) = rewrite_head(&self.head[..end], &self.extra_headers)
+            .map_err(|msg| (HttpStatus::BadGateway, msg))?;
+        self.status = Some(status);
+        self.to_client = head;
+        self.to_client.extend_from_slice(&self.head[end + 4..]);
+        self.head = Vec::new();
+        self.head_done = true;
+        Ok(())
+    }
+
+    // Send what we can of the response. Writes are kept small, since the
+    // client's socket blocks.
+    pub fn write_client<W>(&mut self, stream: &mut W, max: usize) -> Result<usize, io::Error>
+    where
+        W: Write,
+    {
+        let len = self.to_client.len().min(max);
+        let amt = stream.write(&self.to_client[..len])?;
+        self.to_client.drain(..amt);
+        Ok(amt)
+    }
+}
+
+// Whether `name` (lowercase) may be passed on, given the Connection header of
+// the same message, which can name more hop-by-hop headers.
+fn is_end_to_end(name: &str, connection: Option<&str>) -> bool {
+    if HOP_BY_HOP_HEADERS.contains(&name) {
+        return false;
+    }
+    match connection {
+        Some(connection) => !connection
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case(name)),
+        None => true,
+    }
+}
+
+// Percent-encode a decoded request path, so that it can be sent on
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~!$&'()*+,;=:@".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// Turn the upstream's response head into the one sent to the client: the
+// same status and end-to-end headers, on a connection that closes after.
+// Returns the status code too.
+fn rewrite_head(
+    head: &[u8],
+    extra_headers: &[(String, String)],
+) -> Result<(String, Vec<u8>), String> {
+    let malformed = || "The upstream server sent a malformed response.".to_string();
+    let head = std::str::from_utf8(head).map_err(|_| malformed())?;
+    let mut lines = head.split("\r\n");
+
+    let status_line = lines.next().unwrap_or("");
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    let code = parts.next().unwrap_or("");
+    let reason = parts.next().unwrap_or("");
+    if !version.starts_with("HTTP/1.")
+        || code.len() != 3
+        || !code.bytes().all(|b| b.is_ascii_digit())
+    {
+        return Err(malformed());
+    }
+
+    let mut headers = Vec::new();
+    for line in lines {
+        let colon = line.find(':').ok_or_else(malformed)?;
+        headers.push((line[..colon].trim(), line[colon + 1..].trim()));
+    }
+    let connection = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("connection"))
+        .map(|(_, value)| *value);
+
+    let mut rewritten = format!("HTTP/1.1 {} {}\r\n", code, reason);
+    for (name, value) in &headers {
+        let replaced = extra_headers
+            .iter()
+            .any(|(extra, _)| extra.eq_ignore_ascii_case(name));
+        if is_end_to_end(&name.to_lowercase(), connection) && !replaced {
+            rewritten.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    for (name, value) in extra_headers {
+        rewritten.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    rewritten.push_str("Connection: close\r\n\r\n");
+    Ok((code.to_string(), rewritten.into_bytes()))
+}
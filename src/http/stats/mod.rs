@@ -0,0 +1,53 @@
+use std::collections::BTreeSet;
+
+// Totals for the whole session, reported when the server shuts down.
+pub struct ServerStats {
+    requests: u64,
+    bytes_served: u64,
+    // Ordered so the report lists them alphabetically
+    paths: BTreeSet<String>,
+    uploads: Vec<String>,
+}
+
+impl ServerStats {
+    pub fn new() -> ServerStats {
+        ServerStats {
+            requests: 0,
+            bytes_served: 0,
+            paths: BTreeSet::new(),
+            uploads: Vec::new(),
+        }
+    }
+
+    pub fn add_request(&mut self) { self.requests += 1; }
+
+    pub fn add_bytes(&mut self, amount: usize) { self.bytes_served += amount as u64; }
+
+    // A path that was answered successfully
+    pub fn add_path(&mut self, path: &str) {
+        if !self.paths.contains(path) {
+            self.paths.insert(path.to_string());
+        }
+    }
+
+    pub fn add_uploads(&mut self, files: &[String]) { self.uploads.extend_from_slice(files); }
+
+    // One line for each part of the report
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Session summary: {} requests, {} bytes served, {} unique paths, {} files uploaded",
+            self.requests,
+            self.bytes_served,
+            self.paths.len(),
+            self.uploads.len()
+        )];
+        if !self.paths.is_empty() {
+            let paths: Vec<&str> = self.paths.iter().map(|p| &p[..]).collect();
+            lines.push(format!("Paths served: {}", paths.join(", ")));
+        }
+        if !self.uploads.is_empty() {
+            lines.push(format!("Files uploaded: {}", self.uploads.join(", ")));
+        }
+        lines
+    }
+}
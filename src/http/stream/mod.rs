@@ -0,0 +1,15 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpStream},
+};
+
+// Anything a connection can be served over. Connections from the listener are
+// TcpStreams, but requests can be driven over any other Read + Write (such as
+// an in-memory buffer) as long as it can name its peer.
+pub trait HttpStream: io::Read + io::Write {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl HttpStream for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> { TcpStream::peer_addr(self) }
+}
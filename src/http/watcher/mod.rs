@@ -0,0 +1,104 @@
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::io::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+// Watches every directory under the root for changes. inotify is not
+// recursive, so each directory gets its own watch, and directories created
+// later are added as they appear.
+pub struct Watcher {
+    inotify: Inotify,
+    root: PathBuf,
+    // Maps each watch to the directory it covers, relative to the root
+    watches: HashMap<WatchDescriptor, String>,
+}
+
+impl Watcher {
+    pub fn new(root: &Path) -> Result<Watcher, nix::Error> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let mut watcher = Watcher {
+            inotify: inotify,
+            root: root.to_path_buf(),
+            watches: HashMap::new(),
+        };
+        watcher.add_tree("")?;
+        Ok(watcher)
+    }
+
+    pub fn get_fd(&self) -> RawFd { self.inotify.as_raw_fd() }
+
+    // Add watches for `relative_dir` and every directory below it.
+    fn add_tree(&mut self, relative_dir: &str) -> Result<(), nix::Error> {
+        let flags = AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_ONLYDIR
+            | AddWatchFlags::IN_DONT_FOLLOW;
+        let dir = self.root.join(relative_dir.trim_start_matches('/'));
+        let wd = self.inotify.add_watch(&dir, flags)?;
+        self.watches.insert(wd, relative_dir.to_string());
+
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.filter_map(Result::ok) {
+                // `file_type` does not follow symlinks, so links can neither
+                // escape the root nor create cycles.
+                let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                if let (true, Some(name)) = (is_dir, entry.file_name().to_str()) {
+                    // Running out of watches is not fatal; that part of the
+                    // tree just won't be reported.
+                    let _ = self.add_tree(&format!("{}/{}", relative_dir, name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Drain pending notifications, returning the paths (relative to the root,
+    // with a leading '/') that changed.
+    pub fn read_changes(&mut self) -> Vec<String> {
+        let mut changes = Vec::<String>::new();
+        while let Ok(events) = self.inotify.read_events() {
+            if events.is_empty() {
+                break;
+            }
+            for event in events {
+                if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                    // Events were dropped; all we know is that something changed.
+                    changes.push("/".to_string());
+                    continue;
+                }
+                if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                    self.watches.remove(&event.wd);
+                    continue;
+                }
+                let dir = match self.watches.get(&event.wd) {
+                    Some(dir) => dir.clone(),
+                    None => {
+                        continue;
+                    }
+                };
+                let path = match event.name {
+                    Some(name) => format!("{}/{}", dir, name.to_string_lossy()),
+                    None => format!("{}/", dir),
+                };
+                if event.mask.contains(AddWatchFlags::IN_ISDIR)
+                    && (event.mask.contains(AddWatchFlags::IN_CREATE)
+                        || event.mask.contains(AddWatchFlags::IN_MOVED_TO))
+                {
+                    let _ = self.add_tree(&path);
+                }
+                if !changes.contains(&path) {
+                    changes.push(path);
+                }
+            }
+        }
+        changes
+    }
+}
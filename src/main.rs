@@ -11,14 +11,16 @@ use display::{
     display,
     types::{ConnectionSet, ControlEvent},
 };
-use http::HttpTui;
+use http::{control::ControlCommand, HttpTui};
 use opts::types::Opts;
 
 use clap::Clap;
 use std::{
+    env,
     fs::canonicalize,
     io,
     path::Path,
+    process,
     sync::{
         atomic::{AtomicBool, Ordering},
         mpsc, Arc, Mutex,
@@ -26,11 +28,33 @@ use std::{
     thread,
 };
 
-use nix::unistd;
+use nix::{
+    sys::signal::{SigSet, Signal},
+    unistd,
+};
 use termion::{event::Key, input::TermRead};
 
 fn main() -> Result<(), io::Error> {
-    let opts: Opts = Opts::parse();
+    // Bad arguments and --help have been dealt with by the first parse, so
+    // the second can only fail on the --config file.
+    let _: Opts = Opts::parse();
+    let opts = match opts::parse_with_config(env::args().collect()) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            process::exit(1);
+        }
+    };
+
+    // SIGHUP reloads the configuration. It is blocked before any threads
+    // start, so that it is only taken by the thread waiting for it.
+    let mut hangup = SigSet::empty();
+    hangup.add(Signal::SIGHUP);
+    if let Err(e) = hangup.thread_block() {
+        eprintln!("Could not block SIGHUP: {}", e);
+        return Ok(());
+    }
+
     let path = Path::new(&opts.directory);
     let canon_path = match canonicalize(path) {
         Ok(path) => path,
@@ -43,12 +67,16 @@ fn main() -> Result<(), io::Error> {
     opts::verify_opts(&opts);
 
     let (hist_tx, hist_rx) = mpsc::channel();
+    // Borrowed by the callbacks below, so that whatever is left in the
+    // history (like the --summary-on-exit summary) can be printed after the
+    // server stops.
+    let hist_rx = &hist_rx;
 
     let mut tui = match HttpTui::new(&canon_path.as_path(), hist_tx, &opts) {
         Ok(tui) => tui,
         Err(e) => {
-            eprintln!("Failed to bind to port {}: {}", opts.port, e);
-            return Ok(());
+            eprintln!("Error: {}", e);
+            process::exit(1);
         }
     };
 
@@ -60,6 +88,16 @@ fn main() -> Result<(), io::Error> {
         }
     };
 
+    tui.set_reloader(|| opts::parse_with_config(env::args().collect()));
+    thread::spawn(move || {
+        while hangup.wait() == Ok(Signal::SIGHUP) {
+            // The pipe is closed once the server is stopping
+            if ControlCommand::Reload.send(write_end).is_err() {
+                break;
+            }
+        }
+    });
+
     let smart_terminal = term::check_terminal();
     if smart_terminal && !opts.headless {
         let connection_set = Arc::new(Mutex::new(ConnectionSet::new()));
@@ -72,6 +110,7 @@ fn main() -> Result<(), io::Error> {
         let connection_set_ptr = connection_set.clone();
         let canon_path = canon_path.clone();
         let opts_c = opts.clone();
+        let disabled = tui.get_disabled_flag();
         let thd = thread::spawn(move || {
             match display(
                 canon_path.display(),
@@ -79,6 +118,7 @@ fn main() -> Result<(), io::Error> {
                 rx,
                 &needs_update_clone,
                 write_end,
+                disabled,
                 &opts_c,
             ) {
                 Err(e) => {
@@ -107,6 +147,9 @@ fn main() -> Result<(), io::Error> {
                         Key::Char(' ') => {
                             let _ = tx.send(ControlEvent::Toggle);
                         }
+                        Key::Char('r') => {
+                            let _ = tx.send(ControlEvent::Reload);
+                        }
                         _ => {}
                     }
                 }
@@ -138,6 +181,13 @@ fn main() -> Result<(), io::Error> {
 
         let _ = thd.join();
         let _ = keys.join();
+
+        // The interface is gone by now, so print the summary instead
+        if opts.summary_on_exit {
+            for line in hist_rx.try_iter() {
+                println!("{}", line);
+            }
+        }
     } else {
         if !opts.headless {
             println!("Warning: terminal is dumb, switching to headless.");
@@ -154,6 +204,9 @@ fn main() -> Result<(), io::Error> {
             }
         });
         let _ = unistd::close(read_end);
+        for line in hist_rx.try_iter() {
+            println!("{}", line);
+        }
     }
 
     Ok(())
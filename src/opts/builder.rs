@@ -0,0 +1,82 @@
+use super::{check_opts, types::Opts};
+
+use clap::Clap;
+
+// Builds `Opts` in code rather than from the command line, for embedding
+// hypershare. Anything not set keeps its command line default.
+pub struct OptsBuilder {
+    opts: Opts,
+}
+
+// Only used when hypershare is embedded; the binary parses its arguments.
+#[allow(dead_code)]
+impl OptsBuilder {
+    pub fn new() -> OptsBuilder {
+        OptsBuilder {
+            opts: Opts::parse_from(["hypershare"]),
+        }
+    }
+
+    pub fn directory(mut self, directory: &str) -> OptsBuilder {
+        self.opts.directory = directory.to_string();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> OptsBuilder {
+        self.opts.port = port;
+        self
+    }
+
+    pub fn hostmask(mut self, hostmask: &str) -> OptsBuilder {
+        self.opts.hostmask = hostmask.to_string();
+        self
+    }
+
+    pub fn uploading(mut self, enabled: bool) -> OptsBuilder {
+        self.opts.uploading_enabled = enabled;
+        self
+    }
+
+    // In bytes, 0 for no limit
+    pub fn size_limit(mut self, limit: usize) -> OptsBuilder {
+        self.opts.size_limit = limit;
+        self
+    }
+
+    pub fn index_file(mut self, index_file: &str) -> OptsBuilder {
+        self.opts.index_file = index_file.to_string();
+        self
+    }
+
+    pub fn directory_listings(mut self, enabled: bool) -> OptsBuilder {
+        self.opts.disable_directory_listings = !enabled;
+        self
+    }
+
+    // Comma separated, as with --methods
+    pub fn methods(mut self, methods: &str) -> OptsBuilder {
+        self.opts.methods = methods.to_string();
+        self
+    }
+
+    pub fn header(mut self, header: &str) -> OptsBuilder {
+        self.opts.headers.push(header.to_string());
+        self
+    }
+
+    pub fn start_disabled(mut self, disabled: bool) -> OptsBuilder {
+        self.opts.start_disabled = disabled;
+        self
+    }
+
+    // Checks the options the same way as on the command line.
+    pub fn build(self) -> Result<Opts, String> {
+        check_opts(&self.opts)?;
+        Ok(self.opts)
+    }
+}
+
+#[allow(dead_code)]
+impl Opts {
+    pub fn builder() -> OptsBuilder { OptsBuilder::new() }
+}
@@ -1,6 +1,213 @@
+pub mod builder;
 pub mod types;
 
-use std::process;
+use crate::{
+    http::http_core::{is_token_char, method_from_str, status_from_code, HttpMethod},
+    rendering::types::{column_from_str, Column},
+};
+
+use clap::Clap;
+use nix::net::if_::if_nametoindex;
+
+use std::{
+    fs,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs},
+    process,
+};
+
+pub fn parse_methods(methods: &str) -> Result<Vec<HttpMethod>, String> {
+    let mut parsed = Vec::new();
+    for name in methods.split(",") {
+        match method_from_str(&name.trim().to_uppercase()) {
+            Some(method) => {
+                if !parsed.contains(&method) {
+                    parsed.push(method);
+                }
+            }
+            None => {
+                return Err(format!("unsupported method '{}'", name.trim()));
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+// Split a list of extensions like "pdf,.ZIP" into ["pdf", "zip"]
+pub fn parse_extensions(extensions: &str) -> Vec<String> {
+    extensions
+        .split(",")
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .collect()
+}
+
+pub fn parse_columns(columns: &str) -> Result<Vec<Column>, String> {
+    let mut parsed = Vec::new();
+    for name in columns.split(",") {
+        match column_from_str(&name.trim().to_lowercase()) {
+            Some(column) => {
+                if !parsed.contains(&column) {
+                    parsed.push(column);
+                }
+            }
+            None => {
+                return Err(format!("unknown column '{}'", name.trim()));
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+// Split a "Name: Value" header. Anything that could end the header early, and
+// so inject another, is rejected.
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    let colon = match header.find(":") {
+        Some(idx) => idx,
+        None => {
+            return Err(format!("'{}' is not of the form \"Name: Value\"", header));
+        }
+    };
+    let name = header[..colon].trim();
+    let value = header[colon + 1..].trim();
+    if name.is_empty() || !name.bytes().all(is_token_char) {
+        return Err(format!("invalid header name '{}'", name));
+    }
+    if value.chars().any(|c| c.is_control() && c != '\t') {
+        return Err(format!("header '{}' contains control characters", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+// Where to listen for a --hostmask and --port. The hostmask is an IPv4
+// address, an IPv6 address (optionally in brackets, and with a zone, as in
+// "fe80::1%eth0"), or a hostname to look up.
+pub fn parse_bind_address(hostmask: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let host = hostmask
+        .strip_prefix("[")
+        .and_then(|host| host.strip_suffix("]"))
+        .unwrap_or(hostmask);
+    let (ip, zone) = match host.find("%") {
+        Some(idx) => (&host[..idx], Some(&host[idx + 1..])),
+        None => (host, None),
+    };
+
+    if let Ok(ip) = ip.parse::<Ipv6Addr>() {
+        let scope_id = match zone {
+            // Either the interface's index or its name
+            Some(zone) => match zone.parse::<u32>() {
+                Ok(index) => index,
+                Err(_) => {
+                    if_nametoindex(zone).map_err(|_| format!("unknown interface '{}'", zone))?
+                }
+            },
+            None => 0,
+        };
+        return Ok(vec![SocketAddr::V6(SocketAddrV6::new(
+            ip, port, 0, scope_id,
+        ))]);
+    }
+    if zone.is_some() {
+        return Err(format!(
+            "'{}' has a zone, but is not an IPv6 address",
+            hostmask
+        ));
+    }
+    if let Ok(ip) = host.parse::<Ipv4Addr>() {
+        return Ok(vec![SocketAddr::V4(SocketAddrV4::new(ip, port))]);
+    }
+
+    let addrs: Vec<SocketAddr> = match (host, port).to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(e) => {
+            return Err(format!("could not look up '{}': {}", host, e));
+        }
+    };
+    if addrs.is_empty() {
+        return Err(format!("'{}' has no addresses", host));
+    }
+    Ok(addrs)
+}
+
+// Split a --proxy, given as PREFIX=URL, into the prefix, the upstream's
+// host[:port], and the path of the URL if it has one.
+pub fn parse_proxy(proxy: &str) -> Result<(String, String, Option<String>), String> {
+    let eq = match proxy.find("=") {
+        Some(idx) => idx,
+        None => {
+            return Err(format!("'{}' is not of the form PREFIX=URL", proxy));
+        }
+    };
+    let prefix = &proxy[..eq];
+    let url = &proxy[eq + 1..];
+    if !prefix.starts_with("/") || prefix.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return Err(format!("invalid prefix '{}'", prefix));
+    }
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None => {
+            return Err(format!("'{}' is not an http:// URL", url));
+        }
+    };
+    let (authority, path) = match rest.find("/") {
+        Some(idx) => (&rest[..idx], Some(&rest[idx..])),
+        None => (rest, None),
+    };
+    if authority.is_empty()
+        || authority.contains("@")
+        || !authority.chars().all(|c| c.is_ascii_graphic())
+        || path.is_some_and(|path| path.contains(|c: char| c.is_control() || c == ' '))
+    {
+        return Err(format!("invalid URL '{}'", url));
+    }
+    Ok((
+        prefix.to_string(),
+        authority.to_string(),
+        path.map(|path| path.to_string()),
+    ))
+}
+
+// Give a --base-path a leading '/' and no trailing one, so that "share/"
+// becomes "/share", and "/" becomes "".
+pub fn normalize_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+// Parse a command line, and then again with the options from its --config
+// file added to the end.
+pub fn parse_with_config(args: Vec<String>) -> Result<types::Opts, String> {
+    let opts = types::Opts::try_parse_from(&args).map_err(|e| clap_message(&e))?;
+    let path = match &opts.config {
+        Some(path) => path.clone(),
+        None => return Ok(opts),
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("could not read --config {}: {}", path, e))?;
+    let mut args = args;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Values are taken as they are, spaces and all
+        args.extend(
+            line.splitn(2, char::is_whitespace)
+                .map(|arg| arg.trim().to_string()),
+        );
+    }
+    types::Opts::try_parse_from(&args)
+        .map_err(|e| format!("invalid --config {}: {}", path, clap_message(&e)))
+}
+
+// The first line of a clap error, without its usage and help
+fn clap_message(e: &clap::Error) -> String {
+    let message = e.to_string();
+    let first_line = message.lines().next().unwrap_or_default();
+    first_line.trim_start_matches("error: ").to_string()
+}
 
 pub fn verify_opts(opts: &types::Opts) {
     if opts.start_disabled && opts.headless {
@@ -10,8 +217,130 @@ pub fn verify_opts(opts: &types::Opts) {
         );
     }
 
-    if opts.index_file.contains("/") {
-        println!("Error: invalid index file.");
+    if let Err(e) = check_opts(opts) {
+        println!("Error: {}.", e);
         process::exit(1);
     }
 }
+
+// Everything that clap cannot check by itself, shared by the command line and
+// `OptsBuilder`.
+pub fn check_opts(opts: &types::Opts) -> Result<(), String> {
+    if opts.index_file.contains("/") {
+        return Err("invalid index file".to_string());
+    }
+
+    if let Err(e) = parse_methods(&opts.methods) {
+        return Err(format!("invalid --methods: {}", e));
+    }
+
+    match parse_columns(&opts.columns) {
+        Ok(columns) => {
+            let counting = opts.count_downloads || opts.downloads_file.is_some();
+            if columns.contains(&Column::Downloads) && !counting {
+                return Err(
+                    "invalid --columns: the downloads column needs --count-downloads".to_string(),
+                );
+            }
+        }
+        Err(e) => {
+            return Err(format!("invalid --columns: {}", e));
+        }
+    }
+
+    for header in &opts.headers {
+        if let Err(e) = parse_header(header) {
+            return Err(format!("invalid --header: {}", e));
+        }
+    }
+
+    if !opts.default_mime.contains("/") || opts.default_mime.chars().any(|c| c.is_control()) {
+        return Err(format!("invalid --default-mime: '{}'", opts.default_mime));
+    }
+
+    if let Some(ext) = &opts.force_upload_ext {
+        let ext = ext.trim_start_matches('.');
+        if ext.is_empty() || ext.contains("/") {
+            return Err(format!("invalid --force-upload-ext: '{}'", ext));
+        }
+    }
+
+    if let Some(credentials) = &opts.admin_credentials {
+        if !credentials.contains(":") {
+            return Err("invalid --admin-credentials: expected user:password".to_string());
+        }
+    }
+
+    // The prefix ends up in links and headers as is
+    if opts
+        .base_path
+        .chars()
+        .any(|c| c.is_control() || c.is_whitespace() || "'\"<>?#".contains(c))
+    {
+        return Err(format!("invalid --base-path: '{}'", opts.base_path));
+    }
+
+    for proxy in &opts.proxies {
+        if let Err(e) = parse_proxy(proxy) {
+            return Err(format!("invalid --proxy: {}", e));
+        }
+    }
+
+    // Anything else would look like the request went through
+    match status_from_code(opts.disabled_status) {
+        Some(_) if opts.disabled_status >= 400 => {}
+        _ => {
+            return Err(format!(
+                "invalid --disabled-status: {} is not a supported error status",
+                opts.disabled_status
+            ));
+        }
+    }
+
+    if opts.max_header_size == 0 {
+        return Err("invalid --max-header-size: must be greater than 0".to_string());
+    }
+    if opts.max_path_depth == 0 {
+        return Err("invalid --max-path-depth: must be greater than 0".to_string());
+    }
+    if opts.max_component_length == 0 {
+        return Err("invalid --max-component-length: must be greater than 0".to_string());
+    }
+    if opts.max_ranges == 0 {
+        return Err("invalid --max-ranges: must be greater than 0".to_string());
+    }
+    if opts.backlog == 0 || opts.backlog > i32::MAX as usize {
+        return Err("invalid --backlog: must be between 1 and 2147483647".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    #[test]
+    fn bind_address_parsing() {
+        let lo = nix::net::if_::if_nametoindex("lo").unwrap();
+        let zoned = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 80, 0, lo));
+        assert_eq!(parse_bind_address("fe80::1%lo", 80), Ok(vec![zoned]));
+        assert_eq!(parse_bind_address("[fe80::1%lo]", 80), Ok(vec![zoned]));
+        assert_eq!(
+            parse_bind_address(&format!("fe80::1%{}", lo), 80),
+            Ok(vec![zoned])
+        );
+        assert_eq!(
+            parse_bind_address("::1", 80),
+            Ok(vec![SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 80)])
+        );
+        assert_eq!(
+            parse_bind_address("127.0.0.1", 80),
+            Ok(vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 80)])
+        );
+        assert!(!parse_bind_address("localhost", 80).unwrap().is_empty());
+        assert!(parse_bind_address("fe80::1%nosuchinterface", 80).is_err());
+        assert!(parse_bind_address("127.0.0.1%lo", 80).is_err());
+    }
+}
@@ -19,6 +19,13 @@ pub struct Opts {
                  enabled."
     )]
     pub start_disabled: bool,
+    #[clap(
+        long = "disabled-status",
+        about = "Status code returned while the server is disabled, e.g. 403 or 423, for health \
+                 checkers that treat a 503 as a reason to restart it.",
+        default_value = "503"
+    )]
+    pub disabled_status: u16,
     #[clap(
         short = 'r',
         long = "ui-refresh-rate",
@@ -48,7 +55,324 @@ pub struct Opts {
     #[clap(
         long = "no-slash",
         about = "When navigating to a directory, hypershare will not try to append a '/' to the \
-                 path."
+                 path. Index files are still served, but relative links in them resolve against \
+                 the parent directory unless the '/' is given."
     )]
     pub no_append_slash: bool,
+    #[clap(
+        long = "no-symlink-slash",
+        about = "Do not append a '/' to paths of symbolic links to directories."
+    )]
+    pub no_symlink_slash: bool,
+    #[clap(
+        long = "follow-symlinks",
+        about = "Which symbolic links to follow: none (off), those pointing inside the served \
+                 directory (internal), or all of them, even those pointing outside it (all).",
+        default_value = "internal",
+        possible_values = &["off", "internal", "all"]
+    )]
+    pub follow_symlinks: String,
+    #[clap(
+        long = "no-ranges",
+        about = "Ignore Range headers and do not advertise Accept-Ranges. Files are always sent \
+                 in full."
+    )]
+    pub no_ranges: bool,
+    #[clap(
+        long = "methods",
+        about = "Comma-separated list of HTTP methods to serve. Any other method receives a 405.",
+        default_value = "GET,HEAD,POST,OPTIONS"
+    )]
+    pub methods: String,
+    #[clap(
+        long = "robots",
+        about = "Policy of the default /robots.txt, served when the directory does not contain \
+                 one.",
+        default_value = "disallow",
+        possible_values = &["disallow", "allow", "none"]
+    )]
+    pub robots: String,
+    #[clap(
+        long = "security-contact",
+        about = "Serve a default /.well-known/security.txt with this contact (e.g. \
+                 mailto:admin@example.com)."
+    )]
+    pub security_contact: Option<String>,
+    #[clap(
+        long,
+        about = "Watch the directory for changes and announce them as server-sent events on \
+                 /__events."
+    )]
+    pub watch: bool,
+    #[clap(
+        long = "columns",
+        about = "Comma-separated list of columns to show in directory listings, in order. Columns \
+                 are type, name, size, mtime, checksum, and downloads (which needs \
+                 --count-downloads).",
+        default_value = "type,name,size,checksum"
+    )]
+    pub columns: String,
+    #[clap(
+        long = "nosniff",
+        about = "Send X-Content-Type-Options: nosniff with every response, so browsers do not \
+                 guess content types."
+    )]
+    pub nosniff: bool,
+    #[clap(
+        long = "header",
+        about = "Add a header to every response, given as \"Name: Value\". May be repeated.",
+        number_of_values = 1
+    )]
+    pub headers: Vec<String>,
+    #[clap(
+        long = "no-auto-nosniff",
+        about = "Do not send X-Content-Type-Options: nosniff with files of unknown type."
+    )]
+    pub no_auto_nosniff: bool,
+    #[clap(
+        long = "compress",
+        about = "Compress text responses of up to 1 MiB with Brotli or gzip for clients that \
+                 accept it."
+    )]
+    pub compress: bool,
+    #[clap(
+        long = "compress-min-size",
+        about = "With --compress, responses smaller than this many bytes are sent uncompressed.",
+        default_value = "1024"
+    )]
+    pub compress_min_size: usize,
+    #[clap(
+        long = "no-listing-for-root",
+        about = "Disable the directory listing for the root only. Subdirectories are still \
+                 listed, and an index file in the root is still served."
+    )]
+    pub no_root_listing: bool,
+    #[clap(
+        long = "fast-reject-uploads",
+        about = "Close the connection as soon as an upload is found to be invalid, rather than \
+                 reading the rest of it first. Saves bandwidth, but browsers will show a \
+                 connection reset instead of the error message."
+    )]
+    pub fast_reject_uploads: bool,
+    #[clap(
+        long = "count-downloads",
+        about = "Count how many times each file is downloaded, and serve the counts as JSON from \
+                 /_downloads."
+    )]
+    pub count_downloads: bool,
+    #[clap(
+        long = "downloads-file",
+        about = "Save download counts to this JSON file, and load them from it at startup. \
+                 Implies --count-downloads."
+    )]
+    pub downloads_file: Option<String>,
+    #[clap(
+        long = "max-header-size",
+        about = "Largest request line and headers accepted, in bytes. Larger requests receive a \
+                 431.",
+        default_value = "4096"
+    )]
+    pub max_header_size: usize,
+    #[clap(
+        long = "discard-limit",
+        about = "After an upload fails, read and discard at most this many more bytes of it so \
+                 that the error can be delivered, then close the connection. Specify 0 for no \
+                 limit.",
+        default_value = "16777216"
+    )]
+    pub discard_limit: usize,
+    #[clap(
+        long = "force-upload-ext",
+        about = "Store uploaded files with this extension appended (e.g. \"upload\" stores \
+                 page.html as page.html.upload), so uploads are not served as pages or scripts."
+    )]
+    pub force_upload_ext: Option<String>,
+    #[clap(
+        long = "default-mime",
+        about = "Content type of files whose type cannot be told from their extension.",
+        default_value = "application/octet-stream"
+    )]
+    pub default_mime: String,
+    #[clap(
+        long = "continue-threshold",
+        about = "Only answer Expect: 100-continue with a 100 for uploads larger than this many \
+                 bytes. Smaller uploads are read straight away.",
+        default_value = "0"
+    )]
+    pub continue_threshold: usize,
+    #[clap(
+        long = "admin-credentials",
+        about = "Enable POST /__admin/toggle, which enables or disables the server, for clients \
+                 sending these Basic Auth credentials, given as user:password."
+    )]
+    pub admin_credentials: Option<String>,
+    #[clap(
+        long = "summary-on-exit",
+        about = "When shutting down, add a summary of the session to the history: requests, bytes \
+                 served, paths served, and files uploaded."
+    )]
+    pub summary_on_exit: bool,
+    #[clap(
+        long = "plus-as-space",
+        about = "Treat '+' in the filenames of form uploads as a space, for clients that encode \
+                 spaces that way. Off by default, since '+' is a valid filename character."
+    )]
+    pub plus_as_space: bool,
+    #[clap(
+        long = "base-path",
+        about = "Path that hypershare is reachable under, when behind a reverse proxy at a \
+                 subpath (e.g. /share). Links in listings and redirects start with it.",
+        default_value = "/"
+    )]
+    pub base_path: String,
+    #[clap(
+        long = "reject-get-bodies",
+        about = "Answer GET and HEAD requests that have a body with a 400. By default they are \
+                 served, and the connection is closed afterwards."
+    )]
+    pub reject_get_bodies: bool,
+    #[clap(
+        long = "cgi-dir",
+        about = "Run files in this directory (relative to the served directory) as CGI scripts \
+                 instead of serving them. Scripts are run with GET, HEAD, and POST, even when \
+                 uploading is disabled."
+    )]
+    pub cgi_dir: Option<String>,
+    #[clap(
+        long = "proxy",
+        about = "Pass requests under a path prefix on to another HTTP server, given as \
+                 PREFIX=URL (e.g. /api=http://127.0.0.1:8080). If the URL has a path, it \
+                 replaces the prefix. May be repeated.",
+        number_of_values = 1
+    )]
+    pub proxies: Vec<String>,
+    #[clap(
+        long = "decompress-uploads",
+        about = "Store raw uploads sent with \"Content-Encoding: gzip\" decompressed. The size \
+                 limit then applies to the decompressed size."
+    )]
+    pub decompress_uploads: bool,
+    #[clap(
+        long = "listing-header",
+        about = "HTML to add to the top of every directory listing, such as a notice or a <link> \
+                 to a stylesheet. It is inserted as given, without escaping."
+    )]
+    pub listing_header: Option<String>,
+    #[clap(
+        long = "listing-footer",
+        about = "HTML to add to the bottom of every directory listing. It is inserted as given, \
+                 without escaping."
+    )]
+    pub listing_footer: Option<String>,
+    #[clap(
+        long = "max-path-depth",
+        about = "Most directories deep a request path may go. Deeper paths receive a 414 without \
+                 touching the filesystem.",
+        default_value = "64"
+    )]
+    pub max_path_depth: usize,
+    #[clap(
+        long = "max-component-length",
+        about = "Longest file or directory name allowed in a request path, in bytes. Paths with \
+                 longer names receive a 400 without touching the filesystem.",
+        default_value = "255"
+    )]
+    pub max_component_length: usize,
+    #[clap(
+        long = "allow-ext",
+        about = "Comma-separated list of file extensions to serve (e.g. pdf,zip,txt). Other files \
+                 receive a 404 and are left out of listings. Directories are unaffected."
+    )]
+    pub allow_ext: Option<String>,
+    #[clap(
+        long = "deny-ext",
+        about = "Comma-separated list of file extensions never to serve (e.g. exe,sh). They \
+                 receive a 404 and are left out of listings."
+    )]
+    pub deny_ext: Option<String>,
+    #[clap(
+        long = "debug-echo",
+        about = "Accept multipart POSTs to /__echo and reply with a summary of each part (name, \
+                 filename, type and size) instead of storing anything. Works without -u."
+    )]
+    pub debug_echo: bool,
+    #[clap(
+        long = "listing-cache",
+        about = "Keep up to this many rendered directory listings in memory, reusing each until \
+                 its directory is modified. Files changed in place may show stale sizes and times \
+                 until then. Specify 0 to render every listing.",
+        default_value = "0"
+    )]
+    pub listing_cache: usize,
+    #[clap(
+        long = "proxy-timeout",
+        about = "Seconds to wait for an upstream server to start its response before giving up \
+                 with a 504. Specify 0 to wait forever.",
+        default_value = "30"
+    )]
+    pub proxy_timeout: u64,
+    #[clap(
+        long = "root",
+        about = "Another directory to look for files in, after the served directory and any \
+                 earlier --root. The first one containing a requested path serves it. Uploads and \
+                 scripts only use the served directory. May be repeated.",
+        number_of_values = 1
+    )]
+    pub roots: Vec<String>,
+    #[clap(
+        long = "minimal-logging",
+        about = "Leave client addresses out of the history, showing a token for each instead. \
+                 Tokens let one client's requests be told apart from another's, but change every \
+                 time the server starts."
+    )]
+    pub minimal_logging: bool,
+    #[clap(
+        long = "honor-dnt",
+        about = "Leave the addresses of clients that send DNT: 1 out of the history, as \
+                 --minimal-logging does for everyone."
+    )]
+    pub honor_dnt: bool,
+    #[clap(
+        long = "backlog",
+        about = "How many connections the kernel may queue before they are accepted. Raise it for \
+                 bursts of clients, such as load tests. Capped by net.core.somaxconn.",
+        default_value = "128"
+    )]
+    pub backlog: usize,
+    #[clap(
+        long = "read-timeout",
+        about = "Seconds a read from a client may block before its connection is closed. Specify \
+                 0 to wait forever.",
+        default_value = "10"
+    )]
+    pub read_timeout: u64,
+    #[clap(
+        long = "write-timeout",
+        about = "Seconds a write to a client may block, such as when it stops reading a large \
+                 response, before its connection is closed. Nothing else is served in the \
+                 meantime. Specify 0 to wait forever.",
+        default_value = "10"
+    )]
+    pub write_timeout: u64,
+    #[clap(
+        long = "max-ranges",
+        about = "Most ranges one request may ask for. Requests for more are sent the whole file.",
+        default_value = "100"
+    )]
+    pub max_ranges: usize,
+    #[clap(
+        long = "max-range-bytes",
+        about = "Most bytes the ranges of one request may add up to, counting overlaps each time. \
+                 Requests for more are sent the whole file. Specify 0 to allow up to the size of \
+                 the file.",
+        default_value = "0"
+    )]
+    pub max_range_bytes: usize,
+    #[clap(
+        long = "config",
+        about = "Read more options from this file, one per line, as they would be written on the \
+                 command line. Blank lines and lines starting with # are skipped. The file is \
+                 read again when the configuration is reloaded."
+    )]
+    pub config: Option<String>,
 }
@@ -1,11 +1,30 @@
-use std::{fs, path::Path};
+pub mod types;
 
-use std::{collections::HashMap, io::Read};
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
 
-use crate::http::http_core;
+use std::{
+    cmp::min,
+    collections::HashMap,
+    io::{self, Read},
+};
+
+use crate::http::{downloads::DownloadCounter, http_core};
+
+use crate::http::mime::ExtensionFilter;
+
+use types::{column_to_heading, Column, ListingEntry, ListingLayout};
 
 const GIT_HASH: &'static str = env!("GIT_HASH");
 
+// Directory listings show at most this many entries per page by default
+pub const DEFAULT_PAGE_SIZE: usize = 500;
+// Larger requested page sizes are clamped to this
+pub const MAX_PAGE_SIZE: usize = 5000;
+
 struct HtmlElement {
     tag: &'static str,
     attributes: Vec<(String, String)>,
@@ -38,6 +57,13 @@ impl HtmlElement {
         }
     }
 
+    // Markup that is rendered as given, with no tag of its own
+    pub fn fragment(html: String) -> HtmlElement {
+        let mut fragment = HtmlElement::new("", HtmlStyle::NoChildren);
+        fragment.add_text(html);
+        fragment
+    }
+
     pub fn add_text(&mut self, text: String) { self.text = Some(text); }
 
     pub fn add_child(&mut self, child: HtmlElement) {
@@ -56,6 +82,9 @@ impl HtmlElement {
     pub fn add_class(&mut self, class: &'static str) { self.classes.push(class); }
 
     pub fn render(&self) -> String {
+        if self.tag.is_empty() {
+            return self.text.clone().unwrap_or_default();
+        }
         let attributes = if self.attributes.len() > 0 {
             let mut s = format!("");
             for (attr, val) in &self.attributes {
@@ -109,12 +138,14 @@ fn generate_default_footer() -> HtmlElement {
     footer
 }
 
-fn generate_href(relative_path: &str, fname: &str) -> String {
+// `base_path` is where the root is mounted, like "/share", or "" for none.
+fn generate_href(base_path: &str, relative_path: &str, fname: &str) -> String {
     if relative_path.ends_with("/") {
-        format!("/{}{}", relative_path, fname)
+        format!("{}/{}{}", base_path, relative_path, fname)
     } else {
         format!(
-            "/{}{}{}",
+            "{}/{}{}{}",
+            base_path,
             relative_path,
             if relative_path.len() > 0 { "/" } else { "" },
             fname
@@ -122,19 +153,40 @@ fn generate_href(relative_path: &str, fname: &str) -> String {
     }
 }
 
-fn generate_md5_table(paths: &Vec<std::fs::DirEntry>) -> HashMap<String, String> {
+// The entries of a directory, sorted by path. Entries that cannot be read,
+// whose metadata cannot be read, or whose names are not UTF-8 are left out,
+// so that one bad entry does not spoil the whole listing.
+fn read_listing(path: &Path) -> io::Result<Vec<ListingEntry>> {
+    let entries = fs::read_dir(path)?
+        .filter_map(Result::ok)
+        .map(|entry| (entry.file_name(), entry.path(), entry.metadata()));
+    Ok(usable_entries(entries))
+}
+
+pub fn usable_entries(
+    entries: impl Iterator<Item = (OsString, PathBuf, io::Result<fs::Metadata>)>,
+) -> Vec<ListingEntry> {
+    let mut usable: Vec<_> = entries
+        .filter_map(|(name, path, metadata)| {
+            Some(ListingEntry {
+                name: name.into_string().ok()?,
+                path,
+                metadata: metadata.ok()?,
+            })
+        })
+        .collect();
+    usable.sort_by(|a, b| a.path.cmp(&b.path));
+    usable
+}
+
+fn generate_md5_table(entries: &[ListingEntry]) -> HashMap<String, String> {
     let mut res = HashMap::<String, String>::new();
-    for entry in paths {
-        let metadata = match entry.metadata() {
-            Ok(meta) => meta,
-            _ => {
-                continue;
-            }
-        };
+    for entry in entries {
+        let metadata = &entry.metadata;
         if !metadata.is_file() {
             continue;
         }
-        let is_sum = match entry.path().extension() {
+        let is_sum = match entry.path.extension() {
             Some(ext) => ext.to_string_lossy() == "md5sum",
             None => false,
         };
@@ -144,106 +196,182 @@ fn generate_md5_table(paths: &Vec<std::fs::DirEntry>) -> HashMap<String, String>
         if metadata.len() > 34 {
             continue;
         }
-        if let Ok(mut file) = fs::File::open(entry.path()) {
+        if let Ok(mut file) = fs::File::open(&entry.path) {
             let mut contents = String::with_capacity(metadata.len() as usize);
             if file.read_to_string(&mut contents).is_ok() {
-                if let Some(s) = entry.path().file_name().unwrap().to_str() {
-                    res.insert(s.to_string(), contents);
-                }
+                res.insert(entry.name.clone(), contents);
             }
         }
     }
     res
 }
 
-fn generate_dir_table(path: &Path, relative_path: &str) -> HtmlElement {
-    if let Ok(paths) = fs::read_dir(path) {
+// Returns the table for one page of the listing, along with the total number of
+// entries across all pages.
+fn generate_dir_table(
+    path: &Path,
+    base_path: &str,
+    relative_path: &str,
+    page: usize,
+    per_page: usize,
+    layout: &ListingLayout,
+    downloads: Option<&DownloadCounter>,
+) -> (HtmlElement, usize) {
+    let columns = &layout.columns;
+    if let Ok(listing) = read_listing(path) {
         let mut table = HtmlElement::new("table", HtmlStyle::CanHaveChildren);
-        let mut paths_vec: Vec<_> = paths.filter_map(Option::Some).map(|r| r.unwrap()).collect();
-        paths_vec.sort_by_key(|p| p.path());
-        let md5_table = generate_md5_table(&paths_vec);
-        for entry in paths_vec {
-            let fname = entry.file_name();
-            let fname_str = match fname.to_str() {
-                Some(f) => f,
-                _ => {
-                    continue;
-                }
-            };
-
-            if md5_table.contains_key(fname_str) {
-                continue;
-            }
+        let mut header = HtmlElement::new("tr", HtmlStyle::CanHaveChildren);
+        for column in columns {
+            let mut th = HtmlElement::new("th", HtmlStyle::CanHaveChildren);
+            th.add_text(column_to_heading(column).to_string());
+            header.add_child(th);
+        }
+        table.add_child(header);
+        let md5_table = generate_md5_table(&listing);
+        // Filter before paginating so that every page is full
+        let entries: Vec<_> = listing
+            .iter()
+            .filter(|entry| {
+                !md5_table.contains_key(&entry.name) && is_listed(entry, &layout.extensions)
+            })
+            .collect();
+        let total = entries.len();
+        for entry in entries
+            .into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+        {
+            let fname_str = &entry.name[..];
+            let meta = &entry.metadata;
 
             let mut tr = HtmlElement::new("tr", HtmlStyle::CanHaveChildren);
 
-            let meta = match entry.metadata() {
-                Ok(m) => m,
-                _ => {
-                    continue;
-                }
-            };
-
-            let mut td_type = HtmlElement::new("td", HtmlStyle::CanHaveChildren);
-            let mut td_a = HtmlElement::new("td", HtmlStyle::CanHaveChildren);
-            let mut td_size = HtmlElement::new("td", HtmlStyle::CanHaveChildren);
-            let mut td_hash = HtmlElement::new("td", HtmlStyle::CanHaveChildren);
-
-            // Add pre
-            let mut pre_type = HtmlElement::new("pre", HtmlStyle::CanHaveChildren);
-            pre_type.add_text(if meta.is_dir() {
-                "[DIR]".to_string()
-            } else {
-                "[FILE]".to_string()
-            });
-            pre_type.add_attribute(
-                "style".to_string(),
-                "display: block; text-align: center;".to_string(),
-            );
-            td_type.add_child(pre_type);
-
-            // Add anchor
-            let href = generate_href(relative_path, fname_str);
-            let mut a = HtmlElement::new("a", HtmlStyle::CanHaveChildren);
-            a.add_attribute("href".to_string(), href);
-            a.add_text(fname_str.to_string());
-            td_a.add_child(a);
-
-            // Add size
-            let mut pre_size = HtmlElement::new("pre", HtmlStyle::CanHaveChildren);
-            if meta.is_file() {
-                pre_size.add_text(format!("{}", meta.len()));
-            }
-            pre_size.add_attribute(
-                "style".to_string(),
-                "display: block; text-align: right;".to_string(),
-            );
-            td_size.add_child(pre_size);
-
-            match md5_table.get(&format!("{}.md5sum", fname_str)) {
-                Some(data) => {
-                    let mut pre = HtmlElement::new("pre", HtmlStyle::CanHaveChildren);
-                    pre.add_text(format!("MD5: {}", data));
-                    td_hash.add_child(pre);
+            for column in columns {
+                let mut td = HtmlElement::new("td", HtmlStyle::CanHaveChildren);
+                match column {
+                    Column::Type => {
+                        let mut pre_type = HtmlElement::new("pre", HtmlStyle::CanHaveChildren);
+                        pre_type.add_text(if meta.is_dir() {
+                            "[DIR]".to_string()
+                        } else {
+                            "[FILE]".to_string()
+                        });
+                        pre_type.add_attribute(
+                            "style".to_string(),
+                            "display: block; text-align: center;".to_string(),
+                        );
+                        td.add_child(pre_type);
+                    }
+                    Column::Name => {
+                        let href = generate_href(base_path, relative_path, fname_str);
+                        let mut a = HtmlElement::new("a", HtmlStyle::CanHaveChildren);
+                        a.add_attribute("href".to_string(), href);
+                        a.add_text(fname_str.to_string());
+                        td.add_child(a);
+                    }
+                    Column::Size => {
+                        let mut pre_size = HtmlElement::new("pre", HtmlStyle::CanHaveChildren);
+                        if meta.is_file() {
+                            pre_size.add_text(format!("{}", meta.len()));
+                        }
+                        pre_size.add_attribute(
+                            "style".to_string(),
+                            "display: block; text-align: right;".to_string(),
+                        );
+                        td.add_child(pre_size);
+                    }
+                    Column::Mtime => {
+                        let mut pre_mtime = HtmlElement::new("pre", HtmlStyle::CanHaveChildren);
+                        if let Ok(modified) = meta.modified() {
+                            pre_mtime.add_text(http_core::format_iso8601(modified));
+                        }
+                        td.add_child(pre_mtime);
+                    }
+                    Column::Checksum => match md5_table.get(&format!("{}.md5sum", fname_str)) {
+                        Some(data) => {
+                            let mut pre = HtmlElement::new("pre", HtmlStyle::CanHaveChildren);
+                            pre.add_text(format!("MD5: {}", data));
+                            td.add_child(pre);
+                        }
+                        _ => {}
+                    },
+                    Column::Downloads => {
+                        let mut pre_downloads = HtmlElement::new("pre", HtmlStyle::CanHaveChildren);
+                        if let (true, Some(downloads)) = (meta.is_file(), downloads) {
+                            // Counted by canonical path, whatever name the
+                            // file was fetched by
+                            let count = fs::canonicalize(path.join(fname_str))
+                                .map(|canonical| downloads.count(&canonical))
+                                .unwrap_or(0);
+                            pre_downloads.add_text(format!("{}", count));
+                        }
+                        pre_downloads.add_attribute(
+                            "style".to_string(),
+                            "display: block; text-align: right;".to_string(),
+                        );
+                        td.add_child(pre_downloads);
+                    }
                 }
-                _ => {}
+                tr.add_child(td);
             }
-            tr.add_child(td_type);
-            tr.add_child(td_a);
-            tr.add_child(td_size);
-            tr.add_child(td_hash);
 
             table.add_child(tr);
         }
-        table
+        (table, total)
     } else {
         let mut p = HtmlElement::new("p", HtmlStyle::CanHaveChildren);
         p.add_text("Error reading directory".to_string());
-        p
+        (p, 0)
     }
 }
 
-pub fn render_directory(relative_path: &str, path: &Path, show_form: bool) -> String {
+fn generate_page_links(page: usize, per_page: usize, total: usize) -> HtmlElement {
+    let mut p = HtmlElement::new("p", HtmlStyle::CanHaveChildren);
+    let first = min((page - 1).saturating_mul(per_page), total);
+    p.add_text(format!(
+        "Showing entries {}-{} of {}. ",
+        min(first + 1, total),
+        min(first.saturating_add(per_page), total),
+        total
+    ));
+    if page > 1 {
+        let mut a = HtmlElement::new("a", HtmlStyle::CanHaveChildren);
+        a.add_attribute(
+            "href".to_string(),
+            format!("?page={}&per={}", page - 1, per_page),
+        );
+        a.add_text("Previous page".to_string());
+        p.add_child(a);
+    }
+    if first.saturating_add(per_page) < total {
+        if page > 1 {
+            let mut span = HtmlElement::new("span", HtmlStyle::CanHaveChildren);
+            span.add_text(" | ".to_string());
+            p.add_child(span);
+        }
+        let mut a = HtmlElement::new("a", HtmlStyle::CanHaveChildren);
+        a.add_attribute(
+            "href".to_string(),
+            format!("?page={}&per={}", page + 1, per_page),
+        );
+        a.add_text("Next page".to_string());
+        p.add_child(a);
+    }
+    p
+}
+
+// `page` starts at 1. `downloads` holds the download counts, if they are kept.
+#[allow(clippy::too_many_arguments)]
+pub fn render_directory(
+    base_path: &str,
+    relative_path: &str,
+    path: &Path,
+    show_form: bool,
+    page: usize,
+    per_page: usize,
+    layout: &ListingLayout,
+    downloads: Option<&DownloadCounter>,
+) -> String {
     let mut html = HtmlElement::new("html", HtmlStyle::CanHaveChildren);
     html.add_attribute("lang".to_string(), "en".to_string());
     let mut head = HtmlElement::new("head", HtmlStyle::CanHaveChildren);
@@ -267,6 +395,10 @@ pub fn render_directory(relative_path: &str, path: &Path, show_form: bool) -> St
     let mut body = HtmlElement::new("body", HtmlStyle::CanHaveChildren);
     let mut h1 = HtmlElement::new("h1", HtmlStyle::CanHaveChildren);
 
+    if let Some(header) = &layout.header {
+        body.add_child(HtmlElement::fragment(header.clone()));
+    }
+
     // <link rel="shortcut icon" href="data:image/x-icon;," type="image/x-icon">
     let mut link_favi = HtmlElement::new("link", HtmlStyle::NoChildren);
     link_favi.add_attribute("rel".to_string(), "shortcut icon".to_string());
@@ -282,7 +414,7 @@ pub fn render_directory(relative_path: &str, path: &Path, show_form: bool) -> St
     let top_level = relative_path.len() == 0;
     if !top_level {
         let mut a = HtmlElement::new("a", HtmlStyle::CanHaveChildren);
-        let href = generate_href(relative_path, "..");
+        let href = generate_href(base_path, relative_path, "..");
         a.add_attribute("href".to_string(), href);
         let mut i = HtmlElement::new("i", HtmlStyle::CanHaveChildren);
         i.add_text("Up a directory".to_string());
@@ -290,8 +422,19 @@ pub fn render_directory(relative_path: &str, path: &Path, show_form: bool) -> St
         body.add_child(a);
         body.add_child(HtmlElement::new("br", HtmlStyle::NoChildren));
     }
-    let table = generate_dir_table(path, relative_path);
+    let (table, total) = generate_dir_table(
+        path,
+        base_path,
+        relative_path,
+        page,
+        per_page,
+        layout,
+        downloads,
+    );
     body.add_child(table);
+    if total > per_page || page > 1 {
+        body.add_child(generate_page_links(page, per_page, total));
+    }
 
     if show_form {
         let mut upload_form = HtmlElement::new("form", HtmlStyle::CanHaveChildren);
@@ -312,12 +455,41 @@ pub fn render_directory(relative_path: &str, path: &Path, show_form: bool) -> St
         body.add_child(upload_form);
     }
 
+    if let Some(footer) = &layout.footer {
+        body.add_child(HtmlElement::fragment(footer.clone()));
+    }
     body.add_child(generate_default_footer());
     html.add_child(body);
 
     format!("<!DOCTYPE html>{}", html.render())
 }
 
+// A listing with one name per line, and a '/' after directories, for
+// clients that would rather not parse HTML. Checksum files are left out, as
+// they are in HTML listings.
+pub fn render_directory_text(path: &Path, extensions: &ExtensionFilter) -> String {
+    let entries = read_listing(path).unwrap_or_default();
+    let md5_table = generate_md5_table(&entries);
+
+    let mut text = String::new();
+    for entry in &entries {
+        if md5_table.contains_key(&entry.name) || !is_listed(entry, extensions) {
+            continue;
+        }
+        text.push_str(&entry.name);
+        if entry.metadata.is_dir() {
+            text.push('/');
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// Directories are always listed, and files only if they may be served
+fn is_listed(entry: &ListingEntry, extensions: &ExtensionFilter) -> bool {
+    entry.metadata.is_dir() || extensions.allows(&entry.path)
+}
+
 fn create_viewport_meta() -> HtmlElement {
     let mut meta = HtmlElement::new("meta", HtmlStyle::NoChildren);
     meta.add_attribute("name".to_string(), "viewport".to_string());
@@ -329,6 +501,16 @@ fn create_viewport_meta() -> HtmlElement {
     meta
 }
 
+// The same error as `render_error`, for clients that would rather have JSON
+pub fn render_error_json(status: &http_core::HttpStatus, msg: Option<String>) -> String {
+    let message = msg.unwrap_or_else(|| http_core::status_to_message(status).to_string());
+    format!(
+        "{{\"status\": {}, \"message\": \"{}\"}}\n",
+        http_core::status_to_code(status),
+        http_core::escape_json(&message)
+    )
+}
+
 pub fn render_error(status: &http_core::HttpStatus, msg: Option<String>) -> String {
     let mut html = HtmlElement::new("html", HtmlStyle::CanHaveChildren);
     html.add_attribute("lang".to_string(), "en".to_string());
@@ -0,0 +1,53 @@
+use crate::http::mime::ExtensionFilter;
+
+use std::{fs, path::PathBuf};
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum Column {
+    Type,
+    Name,
+    Size,
+    Mtime,
+    Checksum,
+    Downloads,
+}
+
+// How directory listings are laid out. The header and footer come from the
+// command line, so they are trusted and inserted unescaped.
+pub struct ListingLayout {
+    pub columns: Vec<Column>,
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    // Files that are not allowed are left out
+    pub extensions: ExtensionFilter,
+}
+
+// An entry of a directory, with the metadata it is listed by
+pub struct ListingEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub metadata: fs::Metadata,
+}
+
+pub fn column_from_str(s: &str) -> Option<Column> {
+    match s {
+        "type" => Some(Column::Type),
+        "name" => Some(Column::Name),
+        "size" => Some(Column::Size),
+        "mtime" => Some(Column::Mtime),
+        "checksum" => Some(Column::Checksum),
+        "downloads" => Some(Column::Downloads),
+        _ => None,
+    }
+}
+
+pub fn column_to_heading(column: &Column) -> &'static str {
+    match column {
+        Column::Type => "Type",
+        Column::Name => "Name",
+        Column::Size => "Size",
+        Column::Mtime => "Modified",
+        Column::Checksum => "Checksum",
+        Column::Downloads => "Downloads",
+    }
+}